@@ -6,19 +6,22 @@ export function activate(context: vscode.ExtensionContext) {
     let formatter = vscode.languages.registerDocumentFormattingEditProvider('markdown', {
         provideDocumentFormattingEdits(document: vscode.TextDocument): Promise<vscode.TextEdit[]> {
             return new Promise((resolve, reject) => {
-                // 确保文件已保存
-                if (document.isDirty) {
-                    document.save().then(() => {
-                        formatFile(document, context, resolve, reject);
-                    });
-                } else {
-                    formatFile(document, context, resolve, reject);
-                }
+                formatFile(document, context, [], resolve, reject);
             });
         }
     });
 
-    context.subscriptions.push(formatter);
+    // 只格式化与选区相交的段落、标题、代码块等，其余部分保持不变
+    let rangeFormatter = vscode.languages.registerDocumentRangeFormattingEditProvider('markdown', {
+        provideDocumentRangeFormattingEdits(document: vscode.TextDocument, range: vscode.Range): Promise<vscode.TextEdit[]> {
+            return new Promise((resolve, reject) => {
+                const lines = `${range.start.line + 1}:${range.end.line + 1}`;
+                formatFile(document, context, ['--range', lines], resolve, reject);
+            });
+        }
+    });
+
+    context.subscriptions.push(formatter, rangeFormatter);
 }
 
 function getFormatterPath(context: vscode.ExtensionContext): string {
@@ -34,32 +37,80 @@ function getFormatterPath(context: vscode.ExtensionContext): string {
     );
 }
 
+// 格式化工具输出的位置，列按 Unicode 字符计
+interface SourcePosition {
+    line: number;
+    column: number;
+}
+
+interface SourceEdit {
+    span: { start: SourcePosition; end: SourcePosition };
+    new_text: string;
+}
+
+// VS Code 的列按 UTF-16 码元计，需要逐字符换算
+function toPosition(document: vscode.TextDocument, position: SourcePosition): vscode.Position {
+    if (position.line >= document.lineCount) {
+        return document.lineAt(document.lineCount - 1).range.end;
+    }
+    const text = document.lineAt(position.line).text;
+    let character = 0;
+    let column = 0;
+    for (const ch of text) {
+        if (column === position.column) {
+            break;
+        }
+        character += ch.length;
+        column++;
+    }
+    return new vscode.Position(position.line, character);
+}
+
+// 只传递用户显式设置过的配置项，未设置的项交给配置文件与默认值决定
+function getExplicitConfig(): { [key: string]: any } {
+    const config = vscode.workspace.getConfiguration('rustdown-formatter');
+    const explicit: { [key: string]: any } = {};
+    for (const key of ['space_between_zh_and_en', 'space_between_zh_and_num', 'format_code_block', 'format_front_matter', 'format_details_text', 'code_formatters', 'extend_code_formatters', 'bullet_list_marker', 'ordered_list_numbering', 'language_tag', 'language_tag_names', 'emphasis_marker', 'strong_marker']) {
+        const inspected = config.inspect(key);
+        const value = inspected?.workspaceFolderValue ?? inspected?.workspaceValue ?? inspected?.globalValue;
+        if (value !== undefined) {
+            explicit[key] = value;
+        }
+    }
+    return explicit;
+}
+
 function formatFile(
     document: vscode.TextDocument,
     context: vscode.ExtensionContext,
+    extraArgs: string[],
     resolve: (value: vscode.TextEdit[]) => void,
     reject: (reason?: any) => void
 ) {
     const formatterPath = getFormatterPath(context);
-    const config = vscode.workspace.getConfiguration('rustdown-formatter');
-    const configStr = JSON.stringify(config)
+    const configStr = JSON.stringify(getExplicitConfig());
+    // 在文档所在目录运行，使格式化工具能向上查找 rustdown.toml / .rustdownrc.json
+    const cwd = document.uri.scheme === 'file' ? path.dirname(document.fileName) : undefined;
     try {
-        // 直接传递文件路径给格式化工具
-        child_process.execFile(formatterPath, [document.fileName],{env:{...process.env, RUSTDOWN_CONFIG: configStr}}, (error, stdout, stderr) => {
+        // 通过 stdin 传递文档内容，无需先保存文件；只取回最小编辑，保留光标位置与撤销粒度
+        const child = child_process.execFile(formatterPath, [...extraArgs, '--edits', '-'], { cwd, env: { ...process.env, RUSTDOWN_CONFIG: configStr }, maxBuffer: 64 * 1024 * 1024 }, (error, stdout, stderr) => {
             if (error) {
                 vscode.window.showErrorMessage(`格式化失败: ${error.message}`);
                 reject(error);
                 return;
             }
+            // 格式化成功但有警告（如代码块格式化工具运行失败）时提示用户
+            if (stderr.trim()) {
+                vscode.window.showWarningMessage(`格式化警告: ${stderr.trim()}`);
+            }
 
-            // 读取文件获取更新后的内容
-            const formatted = document.getText();
-            const fullRange = new vscode.Range(
-                document.positionAt(0),
-                document.positionAt(document.getText().length)
-            );
-            resolve([vscode.TextEdit.replace(fullRange, formatted)]);
+            const edits: SourceEdit[] = JSON.parse(stdout).edits;
+            resolve(edits.map(edit => vscode.TextEdit.replace(
+                new vscode.Range(toPosition(document, edit.span.start), toPosition(document, edit.span.end)),
+                edit.new_text
+            )));
         });
+        child.stdin?.end(document.getText());
     } catch (error) {
         vscode.window.showErrorMessage(`格式化失败: ${error}`);
         reject(error);
@@ -1,66 +1,390 @@
+use anyhow::{Context, Result, anyhow};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
 use serde::{Serialize, Deserialize};
+use serde_json::{Map, Value};
 use maplit::hashmap;
+use once_cell::sync::Lazy;
 use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+/// 配置文件名，在同一目录下按顺序查找
+pub const CONFIG_FILENAMES: [&str; 2] = ["rustdown.toml", ".rustdownrc.json"];
+
+/// 未写出的字段取 `Config::default()` 中的值，拼错的字段名直接报错
 #[derive(Serialize, Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
 pub struct Config{
     pub space_between_zh_and_en: bool,
     pub space_between_zh_and_num: bool,
     pub format_code_block: bool,
+    /// 是否格式化文档开头的 front matter：YAML 交给 `yml` 对应的格式化工具，
+    /// TOML 解析后重新写出：键按字母排序，字符串统一用双引号，内联表改写为普通的表，空格统一；
+    /// 含注释时保持原样，以免丢失注释
+    pub format_front_matter: bool,
+    /// 是否为 `<details>`、`<summary>` 开头的 HTML 块中标签之间的文字加空格，其余 HTML 始终原样保留
+    pub format_details_text: bool,
     pub code_formatters: HashMap<String, String>,
+    /// 为 true 时，`code_formatters` 中没有的语言仍使用内置的格式化工具表；
+    /// 为 false 时只使用 `code_formatters` 中列出的语言
+    pub extend_code_formatters: bool,
+    /// 无序列表统一使用的标记
+    pub bullet_list_marker: BulletListMarker,
+    /// 有序列表的编号方式
+    pub ordered_list_numbering: OrderedListNumbering,
+    /// 代码块围栏后写回的语言名
+    pub language_tag: LanguageTag,
+    /// `language_tag` 为 normalize 时各语言写回的拼写，键为规范化后的语言名，如 `py = "python"`；
+    /// 没有列出的语言使用规范化后的短名
+    pub language_tag_names: HashMap<String, String>,
+    /// 强调统一使用的定界符
+    pub emphasis_marker: EmphasisMarker,
+    /// 加粗统一使用的定界符
+    pub strong_marker: StrongMarker,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BulletListMarker {
+    /// 保持原来的标记
+    #[serde(rename = "preserve")]
+    Preserve,
+    #[serde(rename = "-")]
+    Dash,
+    #[serde(rename = "*")]
+    Asterisk,
+    #[serde(rename = "+")]
+    Plus,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OrderedListNumbering {
+    /// 保持原来的编号
+    Preserve,
+    /// 每一项都写作 `1.`
+    One,
+    /// 从列表第一项的编号开始依次递增
+    Increasing,
+}
+
+/// 规范化的语言名（如把 `javascript` 写作 `js`）始终用于选择格式化工具，
+/// 这里只决定写回到围栏之后的语言名
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LanguageTag {
+    /// 保持原来的写法
+    Preserve,
+    /// 写作规范化后的名字，或 `language_tag_names` 中指定的拼写
+    Normalize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmphasisMarker {
+    /// 保持原来的定界符
+    #[serde(rename = "preserve")]
+    Preserve,
+    #[serde(rename = "*")]
+    Asterisk,
+    /// 单词内部的强调（如 `foo*bar*`）无法使用 `_`，仍保持 `*`
+    #[serde(rename = "_")]
+    Underscore,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StrongMarker {
+    /// 保持原来的定界符
+    #[serde(rename = "preserve")]
+    Preserve,
+    #[serde(rename = "**")]
+    Asterisk,
+    /// 单词内部的加粗无法使用 `__`，仍保持 `**`
+    #[serde(rename = "__")]
+    Underscore,
 }
 
+/// 内置的语言 -> 代码格式化工具表
+static DEFAULT_CODE_FORMATTERS: Lazy<HashMap<String, String>> = Lazy::new(|| {
+    hashmap! {
+        "rust".to_string()      => "rustfmt".to_string(),
+        "js".to_string()        => "prettier".to_string(),
+        "ts".to_string()        => "prettier".to_string(),
+        "css".to_string()       => "prettier".to_string(),
+        "scss".to_string()      => "prettier".to_string(),
+        "sass".to_string()      => "prettier".to_string(),
+        "less".to_string()      => "prettier".to_string(),
+        "html".to_string()      => "prettier".to_string(),
+        "json".to_string()      => "prettier".to_string(),
+        "yml".to_string()       => "prettier".to_string(),
+        "graphql".to_string()   => "prettier".to_string(),
+        "gql".to_string()       => "prettier".to_string(),
+        "vue".to_string()       => "prettier".to_string(),
+        "angular".to_string()   => "prettier".to_string(),
+        "c".to_string()         => "clang-format".to_string(),
+        "cpp".to_string()       => "clang-format".to_string(),
+        "java".to_string()      => "clang-format".to_string(),
+        "go".to_string()        => "gofmt".to_string(),
+        "py".to_string()        => "black".to_string(),
+        "sh".to_string()        => "shfmt".to_string(),
+        "sql".to_string()       => "sqlfmt".to_string(),
+        "tf".to_string()        => "terraform".to_string(),
+        "lua".to_string()       => "stylua".to_string(),
+        "dart".to_string()      => "dartfmt".to_string(),
+        "php".to_string()       => "php-cs-fixer".to_string(),
+        "isort".to_string()     => "isort".to_string(),
+        "autopep8".to_string()  => "autopep8".to_string(),
+        "yapf".to_string()      => "yapf".to_string(),
+        "scala".to_string()     => "scalafmt".to_string(),
+        "kotlin".to_string()    => "ktfmt".to_string(),
+        // 你可以根据需要继续扩展
+    }
+});
+
 impl Default for Config {
     fn default() -> Self {
-        let code_formatters = hashmap! {
-            "rust".to_string()      => "rustfmt".to_string(),
-            "js".to_string()        => "prettier".to_string(),
-            "ts".to_string()        => "prettier".to_string(),
-            "css".to_string()       => "prettier".to_string(),
-            "scss".to_string()      => "prettier".to_string(),
-            "sass".to_string()      => "prettier".to_string(),
-            "less".to_string()      => "prettier".to_string(),
-            "html".to_string()      => "prettier".to_string(),
-            "json".to_string()      => "prettier".to_string(),
-            "yml".to_string()       => "prettier".to_string(),
-            "graphql".to_string()   => "prettier".to_string(),
-            "gql".to_string()       => "prettier".to_string(),
-            "vue".to_string()       => "prettier".to_string(),
-            "angular".to_string()   => "prettier".to_string(),
-            "c".to_string()         => "clang-format".to_string(),
-            "cpp".to_string()       => "clang-format".to_string(),
-            "java".to_string()      => "clang-format".to_string(),
-            "go".to_string()        => "gofmt".to_string(),
-            "py".to_string()        => "black".to_string(),
-            "sh".to_string()        => "shfmt".to_string(),
-            "sql".to_string()       => "sqlfmt".to_string(),
-            "tf".to_string()        => "terraform".to_string(),
-            "lua".to_string()       => "stylua".to_string(),
-            "dart".to_string()      => "dartfmt".to_string(),
-            "php".to_string()       => "php-cs-fixer".to_string(),
-            "isort".to_string()     => "isort".to_string(),
-            "autopep8".to_string()  => "autopep8".to_string(),
-            "yapf".to_string()      => "yapf".to_string(),
-            "scala".to_string()     => "scalafmt".to_string(),
-            "kotlin".to_string()    => "ktfmt".to_string(),
-            // 你可以根据需要继续扩展
-        };
-
         Config {
             space_between_zh_and_en: true,
             space_between_zh_and_num: true,
             format_code_block: true,
-            code_formatters,
+            format_front_matter: false,
+            format_details_text: false,
+            code_formatters: DEFAULT_CODE_FORMATTERS.clone(),
+            extend_code_formatters: true,
+            bullet_list_marker: BulletListMarker::Preserve,
+            ordered_list_numbering: OrderedListNumbering::Preserve,
+            language_tag: LanguageTag::Preserve,
+            language_tag_names: HashMap::new(),
+            emphasis_marker: EmphasisMarker::Preserve,
+            strong_marker: StrongMarker::Preserve,
+        }
+    }
+}
+
+impl Config {
+    /// 查找语言对应的代码格式化工具
+    pub fn code_formatter(&self, language: &str) -> Option<&str> {
+        match self.code_formatters.get(language) {
+            Some(formatter) => Some(formatter),
+            None if self.extend_code_formatters => {
+                DEFAULT_CODE_FORMATTERS.get(language).map(String::as_str)
+            }
+            None => None,
+        }
+    }
+
+    /// 将各层配置按顺序叠加到默认配置上，后面的层优先级更高。
+    /// 各层的 overrides 段不在此处生效，需先用 `ConfigLayer::for_path` 展开
+    pub fn from_layers<'l>(layers: impl IntoIterator<Item = &'l ConfigLayer>) -> Result<Self> {
+        let mut merged = Map::new();
+        for layer in layers {
+            for (key, value) in &layer.values {
+                merged.insert(key.clone(), value.clone());
+            }
+        }
+        Ok(serde_json::from_value(Value::Object(merged))?)
+    }
+
+    /// 读取配置文件，未写出的字段取默认值
+    pub fn from_file(path: &Path) -> Result<Self> {
+        Config::from_layers([&ConfigLayer::from_file(path)?])
+    }
+}
+
+/// 一层配置来源（配置文件、环境变量、命令行参数），只包含其中显式写出的字段
+#[derive(Clone, Debug, Default)]
+pub struct ConfigLayer {
+    values: Map<String, Value>,
+    /// `[[overrides]]` 段，按出现顺序叠加在 `values` 之上
+    overrides: Vec<ConfigOverride>,
+}
+
+/// 只对匹配 `files` 的路径生效的一段配置，类似 editorconfig 的 section
+#[derive(Clone, Debug)]
+struct ConfigOverride {
+    /// `files` 中的 glob 相对于该目录（通常是配置文件所在目录）匹配
+    base: PathBuf,
+    files: GlobSet,
+    values: Map<String, Value>,
+}
+
+impl ConfigLayer {
+    /// 读取配置文件，按扩展名区分 TOML 与 JSON
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("无法读取配置文件 '{}'", path.display()))?;
+        let value = if path.extension().is_some_and(|ext| ext == "toml") {
+            toml::from_str::<Value>(&content).map_err(anyhow::Error::from)
+        } else {
+            serde_json::from_str::<Value>(&content).map_err(anyhow::Error::from)
+        };
+        let base = path.parent().unwrap_or(Path::new("."));
+        value
+            .and_then(|value| ConfigLayer::from_value(value, base))
+            .with_context(|| format!("配置文件 '{}' 无效", path.display()))
+    }
+
+    /// 解析 JSON 字符串形式的配置，其中 overrides 段的 glob 相对于当前目录
+    pub fn from_json_str(json: &str) -> Result<Self> {
+        ConfigLayer::from_value(serde_json::from_str(json)?, Path::new("."))
+    }
+
+    fn from_value(value: Value, base: &Path) -> Result<Self> {
+        let Value::Object(mut values) = value else {
+            return Err(anyhow!("配置必须是一个对象"));
+        };
+        let overrides = match values.remove("overrides") {
+            None => Vec::new(),
+            Some(Value::Array(sections)) => sections
+                .into_iter()
+                .enumerate()
+                .map(|(i, section)| {
+                    ConfigOverride::from_value(section, base)
+                        .with_context(|| format!("overrides 的第 {} 段无效", i + 1))
+                })
+                .collect::<Result<_>>()?,
+            Some(_) => return Err(anyhow!("overrides 必须是数组")),
+        };
+        let layer = ConfigLayer { values, overrides };
+        // 提前与默认配置合并一次，使错误能对应到出错的那一层
+        Config::from_layers([&layer])?;
+        Ok(layer)
+    }
+
+    /// 设置单个字段，用于命令行覆盖
+    pub fn set(&mut self, key: &str, value: Value) -> Result<()> {
+        self.values.insert(key.to_string(), value);
+        Config::from_layers([&*self])?;
+        Ok(())
+    }
+
+    /// 是否包含 `[[overrides]]` 段；没有时该层对所有路径都相同
+    pub fn has_overrides(&self) -> bool {
+        !self.overrides.is_empty()
+    }
+
+    /// 展开该层在 `path` 上实际生效的字段：基础字段依次叠加所有匹配 `path` 的 overrides 段
+    pub fn for_path(&self, path: &Path) -> ConfigLayer {
+        let mut values = self.values.clone();
+        for section in self.overrides.iter().filter(|o| o.matches(path)) {
+            for (key, value) in &section.values {
+                values.insert(key.clone(), value.clone());
+            }
+        }
+        ConfigLayer {
+            values,
+            overrides: Vec::new(),
+        }
+    }
+}
+
+impl ConfigOverride {
+    fn from_value(value: Value, base: &Path) -> Result<Self> {
+        let Value::Object(mut values) = value else {
+            return Err(anyhow!("每一段 overrides 都必须是一个对象"));
+        };
+        let patterns = match values.remove("files") {
+            Some(Value::String(pattern)) => vec![pattern],
+            Some(Value::Array(patterns)) => patterns
+                .into_iter()
+                .map(|pattern| match pattern {
+                    Value::String(pattern) => Ok(pattern),
+                    _ => Err(anyhow!("files 中的每一项都必须是字符串")),
+                })
+                .collect::<Result<_>>()?,
+            _ => return Err(anyhow!("缺少 files 字段（字符串或字符串数组）")),
+        };
+
+        let mut files = GlobSetBuilder::new();
+        for pattern in &patterns {
+            let pattern = pattern.strip_prefix("./").unwrap_or(pattern);
+            // 与 editorconfig 一致，不含 `/` 的模式匹配任意层级下的文件
+            let pattern = if pattern.contains('/') {
+                pattern.to_string()
+            } else {
+                format!("**/{}", pattern)
+            };
+            files.add(
+                GlobBuilder::new(&pattern)
+                    .literal_separator(true)
+                    .build()
+                    .map_err(|e| anyhow!("无效的 glob 模式 '{}': {}", pattern, e))?,
+            );
+        }
+
+        let section = ConfigOverride {
+            base: normalize_path(&std::path::absolute(base)?),
+            files: files.build()?,
+            values,
+        };
+        Config::from_layers([&ConfigLayer {
+            values: section.values.clone(),
+            overrides: Vec::new(),
+        }])?;
+        Ok(section)
+    }
+
+    /// `path` 自身或它在 `base` 之下的某个上级目录匹配 `files` 时生效
+    fn matches(&self, path: &Path) -> bool {
+        let Ok(path) = std::path::absolute(path) else {
+            return false;
+        };
+        let path = normalize_path(&path);
+        let Ok(relative) = path.strip_prefix(&self.base) else {
+            return false;
+        };
+        relative
+            .ancestors()
+            .take_while(|p| !p.as_os_str().is_empty())
+            .any(|p| self.files.is_match(p))
+    }
+}
+
+/// 从 `dir` 开始逐级向上查找配置文件
+pub fn find_config_file(dir: &Path) -> Option<PathBuf> {
+    normalize_path(dir).ancestors().find_map(|dir| {
+        CONFIG_FILENAMES
+            .iter()
+            .map(|name| dir.join(name))
+            .find(|path| path.is_file())
+    })
+}
+
+/// 按字面去掉路径中的 `.` 与 `..`，使 `docs/en/../zh` 与 `docs/zh` 向上查找时经过相同的目录。
+/// 不访问文件系统，因此也不解析符号链接
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match normalized.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normalized.pop();
+                }
+                // 根目录的上级仍是根目录
+                Some(Component::RootDir | Component::Prefix(_)) => {}
+                _ => normalized.push(component),
+            },
+            _ => normalized.push(component),
         }
     }
+    normalized
 }
 
-// impl Config{
-//     pub fn new() -> Self {
-//         Config::default()
-//     }
-//     pub fn from_file(path: &str) -> Result<Self> {
-//         let file_content = std::fs::read_to_string(path)?;
-//         let config: Config = serde_json::from_str(&file_content)?;
-//         Ok(config)
-//     }
-// }
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        let layer = ConfigLayer {
+            values: serde_json::from_str(r#"{"space_between_zh_and_eng": false}"#).unwrap(),
+            overrides: Vec::new(),
+        };
+        assert!(Config::from_layers([&layer]).is_err());
+    }
+
+    #[test]
+    fn normalize_path_resolves_parent_components() {
+        assert_eq!(normalize_path(Path::new("/docs/en/../zh/./a.md")), Path::new("/docs/zh/a.md"));
+        assert_eq!(normalize_path(Path::new("/../a")), Path::new("/a"));
+        assert_eq!(normalize_path(Path::new("../a")), Path::new("../a"));
+    }
+}
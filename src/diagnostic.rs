@@ -0,0 +1,36 @@
+use crate::span::Span;
+
+/// 格式化过程中发现的问题。出现诊断的部分会保持原样输出，
+/// 由调用方决定是展示、忽略还是视为失败。
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    /// 问题在输入中的范围
+    pub span: Span,
+    pub message: String,
+    pub kind: DiagnosticKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum DiagnosticKind {
+    /// 外部代码格式化工具无法启动或运行失败，代码块保持原样
+    ExternalFormatter {
+        /// 代码块在文档中的序号（引用块中的代码块也计算在内），从 0 开始
+        code_block_index: usize,
+        language: String,
+        /// 执行的程序及参数
+        command: Vec<String>,
+        /// 退出码；工具无法启动或被信号终止时为 `None`
+        exit_status: Option<i32>,
+        stderr: String,
+    },
+    /// 代码块没有结束的围栏，会一直延续到文档末尾
+    UnclosedCodeBlock,
+    /// 公式块没有结束的 `$$`，会一直延续到文档末尾
+    UnclosedBlockMath,
+    /// front matter 无法格式化（TOML 解析失败或外部格式化工具运行失败），保持原样
+    FrontMatter {
+        /// 外部格式化工具的 stderr，TOML 解析失败时为空
+        stderr: String,
+    },
+}
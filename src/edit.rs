@@ -0,0 +1,148 @@
+use crate::span::{LineIndex, Span};
+use serde::Serialize;
+use similar::{DiffTag, TextDiff};
+use std::ops::Range;
+use std::time::Duration;
+
+/// 单个字符级差异的计算时限，超时后退化为较粗粒度（但仍然正确）的编辑
+const CHAR_DIFF_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// 对输入的一处修改：把 `span` 范围内的文本替换为 `new_text`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TextEdit {
+    /// 被替换的范围，相对于修改前的输入
+    pub span: Span,
+    pub new_text: String,
+}
+
+/// 计算把 `input` 变为 `output` 所需的最小编辑列表，按位置升序排列且互不重叠。
+/// 编辑器只需应用这些编辑，而不必替换整篇文档，光标位置、折叠与撤销粒度都能保留。
+pub fn text_edits(input: &str, output: &str) -> Vec<TextEdit> {
+    let lines = LineIndex::new(input);
+    let mut edits = Vec::new();
+
+    // 先按行比较，再在变化的行内按字符细化，避免对整篇文档做字符级比较
+    let line_diff = TextDiff::from_lines(input, output);
+    for (old_range, new_range) in changed_ranges(&line_diff) {
+        let old = &input[old_range.clone()];
+        let new = &output[new_range];
+        for (range, new_text) in char_edits(old, new) {
+            edits.push(TextEdit {
+                span: lines.span(range.start + old_range.start..range.end + old_range.start),
+                new_text: new_text.to_string(),
+            });
+        }
+    }
+    edits
+}
+
+/// 差异中各处变化在原文与新文本中的字节范围，按位置升序排列且互不重叠。
+/// `similar` 给出的删除与插入的旧文本下标可能相互矛盾（如删除 `1..3` 之后插入在 `2`），
+/// 因此只使用各操作的长度，由游标依次累加出位置，相邻的删除与插入合并为一处替换
+fn changed_ranges<'a>(diff: &TextDiff<'a, 'a, '_, str>) -> Vec<(Range<usize>, Range<usize>)> {
+    let old_offsets = offsets(diff.old_slices());
+    let new_offsets = offsets(diff.new_slices());
+    let (mut old_index, mut new_index) = (0, 0);
+    let mut ranges: Vec<(Range<usize>, Range<usize>)> = Vec::new();
+    for op in diff.ops() {
+        let (tag, old_range, new_range) = op.as_tag_tuple();
+        let old = old_offsets[old_index]..old_offsets[old_index + old_range.len()];
+        let new = new_offsets[new_index]..new_offsets[new_index + new_range.len()];
+        old_index += old_range.len();
+        new_index += new_range.len();
+        if tag == DiffTag::Equal {
+            continue;
+        }
+        match ranges.last_mut() {
+            Some((last_old, last_new))
+                if last_old.end == old.start && last_new.end == new.start =>
+            {
+                last_old.end = old.end;
+                last_new.end = new.end;
+            }
+            _ => ranges.push((old, new)),
+        }
+    }
+    ranges
+}
+
+/// 各片段在原文中的起始字节偏移，末尾附加总长度
+fn offsets(slices: &[&str]) -> Vec<usize> {
+    let mut offsets = Vec::with_capacity(slices.len() + 1);
+    let mut offset = 0;
+    offsets.push(offset);
+    for slice in slices {
+        offset += slice.len();
+        offsets.push(offset);
+    }
+    offsets
+}
+
+/// `old` 到 `new` 的字符级编辑，范围为 `old` 中的字节偏移
+fn char_edits<'n>(old: &str, new: &'n str) -> Vec<(Range<usize>, &'n str)> {
+    let diff = TextDiff::configure()
+        .timeout(CHAR_DIFF_TIMEOUT)
+        .diff_chars(old, new);
+    changed_ranges(&diff)
+        .into_iter()
+        .map(|(old_range, new_range)| (old_range, &new[new_range]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::pipeline::format_string;
+
+    /// 依次应用编辑，同时检查它们按位置升序排列且互不重叠
+    fn apply(input: &str, edits: &[TextEdit]) -> String {
+        let mut output = String::new();
+        let mut cursor = 0;
+        for edit in edits {
+            assert!(
+                cursor <= edit.span.bytes.start,
+                "编辑重叠或乱序: {:?}",
+                edits
+            );
+            output.push_str(&input[cursor..edit.span.bytes.start]);
+            output.push_str(&edit.new_text);
+            cursor = edit.span.bytes.end;
+        }
+        output.push_str(&input[cursor..]);
+        output
+    }
+
+    #[test]
+    fn edits_rebuild_output() {
+        let inputs = [
+            "> \r\n~~~~~~",
+            "> a\r\n> > b\r\n\r\n```\r\ncode\r\n```\r\n",
+            "中文English\n\n> 引用123\n>\n> ~~~\n> x\n",
+            "~~~~\n```\n~~~\n- a\r\n* b\r\n",
+            "",
+        ];
+        for input in inputs {
+            let (output, _) = format_string(input, &Config::default());
+            assert_eq!(
+                apply(input, &text_edits(input, &output)),
+                output,
+                "输入: {:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn adjacent_delete_and_insert_are_merged() {
+        let edits = text_edits("abc", "axc");
+        assert_eq!(edits.len(), 1);
+        assert_eq!(edits[0].span.bytes, 1..2);
+        assert_eq!(edits[0].new_text, "x");
+    }
+
+    #[test]
+    fn unchanged_input_has_no_edits() {
+        assert!(text_edits("中文 English\n", "中文 English\n").is_empty());
+    }
+}
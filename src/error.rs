@@ -0,0 +1,28 @@
+use std::fmt;
+
+/// `format` / `format_range` 可能返回的错误
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// 格式化过程中发生了内部错误（例如依赖库 panic），输入不会被修改
+    Internal(String),
+    /// `format_range` 的范围无效：起点大于终点，或超出了输入的长度
+    InvalidRange {
+        start: usize,
+        end: usize,
+        len: usize,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Internal(message) => write!(f, "格式化时发生内部错误: {}", message),
+            Error::InvalidRange { start, end, len } => {
+                write!(f, "格式化范围 {}..{} 无效，输入共 {} 字节", start, end, len)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,164 @@
+//! 将命令行输入（文件、目录、glob 模式）展开为待格式化的文件列表
+
+use anyhow::{Result, anyhow};
+use globset::GlobBuilder;
+use ignore::WalkBuilder;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::path::{Path, PathBuf};
+
+/// 遍历目录时视为 markdown 的扩展名
+const MARKDOWN_EXTENSIONS: [&str; 2] = ["md", "markdown"];
+
+/// 项目专用的忽略文件，语法与 `.gitignore` 相同
+const IGNORE_FILENAME: &str = ".rustdownignore";
+
+fn is_markdown(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| {
+            MARKDOWN_EXTENSIONS
+                .iter()
+                .any(|md| ext.eq_ignore_ascii_case(md))
+        })
+}
+
+fn is_glob(input: &str) -> bool {
+    input.contains(['*', '?', '[', '{'])
+}
+
+/// 由 `--exclude` 参数构建匹配器，语法与 `.gitignore` 相同，相对于当前目录
+pub fn build_excludes(patterns: &[String]) -> Result<Gitignore> {
+    let mut builder = GitignoreBuilder::new(".");
+    for pattern in patterns {
+        builder
+            .add_line(None, pattern)
+            .map_err(|e| anyhow!("无效的排除模式 '{}': {}", pattern, e))?;
+    }
+    Ok(builder.build()?)
+}
+
+/// 展开全部输入，返回去重并排序后的文件列表。
+/// 显式给出的文件总会被格式化；目录和 glob 只收集 `.md` / `.markdown` 文件，
+/// 并跳过 `.gitignore`、`.ignore`、`.rustdownignore`、git 的全局忽略文件以及 `--exclude`
+/// 排除的路径。遍历时还会跳过隐藏文件和目录，但显式给出的隐藏目录本身照常遍历。
+pub fn collect_files(inputs: &[String], excludes: &Gitignore) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for input in inputs {
+        let path = Path::new(input);
+        if path.is_file() {
+            files.push(path.to_path_buf());
+        } else if path.is_dir() {
+            walk_dir(path, excludes, |_| true, &mut files)?;
+        } else if is_glob(input) {
+            expand_glob(input, excludes, &mut files)?;
+        } else {
+            return Err(anyhow!("文件 '{}' 不存在", input));
+        }
+    }
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+/// 遍历目录，收集满足 `accept` 的 markdown 文件
+fn walk_dir(
+    dir: &Path,
+    excludes: &Gitignore,
+    accept: impl Fn(&Path) -> bool,
+    files: &mut Vec<PathBuf>,
+) -> Result<()> {
+    let excludes = excludes.clone();
+    let walker = WalkBuilder::new(dir)
+        // 不在 git 仓库中时也遵循 .gitignore
+        .require_git(false)
+        .add_custom_ignore_filename(IGNORE_FILENAME)
+        .filter_entry(move |entry| {
+            let is_dir = entry.file_type().is_some_and(|t| t.is_dir());
+            !excludes.matched(entry.path(), is_dir).is_ignore()
+        })
+        .build();
+    for entry in walker {
+        let entry = entry?;
+        if entry.file_type().is_some_and(|t| t.is_file())
+            && is_markdown(entry.path())
+            && accept(entry.path())
+        {
+            let path = entry.path();
+            files.push(path.strip_prefix(".").unwrap_or(path).to_path_buf());
+        }
+    }
+    Ok(())
+}
+
+/// 从 glob 模式中不含通配符的前缀目录开始遍历，
+/// 收集自身或某个上级目录匹配该模式的 markdown 文件
+fn expand_glob(pattern: &str, excludes: &Gitignore, files: &mut Vec<PathBuf>) -> Result<()> {
+    let pattern = pattern.strip_prefix("./").unwrap_or(pattern);
+    let glob = GlobBuilder::new(pattern)
+        .literal_separator(true)
+        .build()
+        .map_err(|e| anyhow!("无效的 glob 模式 '{}': {}", pattern, e))?
+        .compile_matcher();
+
+    let base: PathBuf = Path::new(pattern)
+        .components()
+        .take_while(|c| !is_glob(&c.as_os_str().to_string_lossy()))
+        .collect();
+    let base = if base.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        base
+    };
+    if !base.is_dir() {
+        return Err(anyhow!("没有文件匹配 '{}'", pattern));
+    }
+
+    let count = files.len();
+    walk_dir(
+        &base,
+        excludes,
+        |path| {
+            // 不带 `./` 的模式也要能匹配以当前目录为起点遍历得到的路径
+            let path = path.strip_prefix(".").unwrap_or(path);
+            path.ancestors()
+                .take_while(|p| !p.as_os_str().is_empty())
+                .any(|p| glob.is_match(p))
+        },
+        files,
+    )?;
+    if files.len() == count {
+        return Err(anyhow!("没有文件匹配 '{}'", pattern));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn hidden_paths_are_skipped_unless_given() {
+        let dir = std::env::temp_dir().join(format!("rustdown-files-{}", std::process::id()));
+        fs::create_dir_all(dir.join(".github")).unwrap();
+        for file in ["a.md", ".hidden.md", ".github/b.md"] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+        let excludes = build_excludes(&[]).unwrap();
+        let collect = |input: PathBuf| {
+            collect_files(&[input.to_string_lossy().into_owned()], &excludes).unwrap()
+        };
+
+        assert_eq!(collect(dir.clone()), vec![dir.join("a.md")]);
+        assert_eq!(collect(dir.join(".github")), vec![dir.join(".github/b.md")]);
+        assert_eq!(
+            collect(dir.join(".github/*.md")),
+            vec![dir.join(".github/b.md")]
+        );
+        assert_eq!(
+            collect(dir.join(".hidden.md")),
+            vec![dir.join(".hidden.md")]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,20 +1,79 @@
-use crate::config::Config;
-use crate::parser::{CodeBlock, Token};
+use crate::config::{
+    BulletListMarker, Config, EmphasisMarker, LanguageTag, OrderedListNumbering, StrongMarker,
+};
+use crate::diagnostic::{Diagnostic, DiagnosticKind};
+use crate::parser::{
+    Alignment, BlockQuote, CodeBlock, Emphasis, EmphasisKind, FrontMatter, FrontMatterKind, Link,
+    ListItem, ListMarker, Parser, Table, Token, TokenKind, html_tag_len,
+};
 use crate::pipeline::format_string;
+use crate::span::Span;
 
-use anyhow::{Result, anyhow};
-use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
 use std::path::Path;
 use std::process::Command;
 use tex_fmt::args::Args;
 use tex_fmt::format::format_file;
 use tex_fmt::logging::Log;
+use unicode_width::UnicodeWidthStr;
+
+/// 强调等容器最外侧的 token，`first` 为 true 时取开头，否则取结尾；其它 token 返回自身
+fn edge_token<'t, 'a>(kind: &'t TokenKind<'a>, first: bool) -> &'t TokenKind<'a> {
+    let TokenKind::Emphasis(emphasis) = kind else {
+        return kind;
+    };
+    let edge = if first {
+        emphasis.content.first()
+    } else {
+        emphasis.content.last()
+    };
+    edge.map_or(kind, |token| edge_token(&token.kind, first))
+}
+
+/// tex-fmt 只把这个路径作为日志中的文件名，不会读写它
+const LATEX_LOG_LABEL: &str = "<markdown math>";
 
 pub struct Formatter<'a> {
     config: &'a Config,
     latex_args: Args,
     latex_logs: Vec<Log>,
     output: String,
+    diagnostics: Vec<Diagnostic>,
+    /// 从外到内各层列表最近一项的输出信息
+    list_levels: Vec<ListLevel>,
+    /// 作为强调的内容格式化时，外层定界符之外是否紧挨着字母或数字（前、后）。
+    /// 内容首尾的强调（如 `***文本***` 中的内层）与外层定界符相连，需一同判断能否使用 `_`
+    words_around: (bool, bool),
+    /// 文档中已输出的代码块数，引用块中的代码块也计算在内，用作诊断信息中代码块的序号
+    code_block_count: usize,
+}
+
+/// 某一层列表最近输出的一项
+struct ListLevel {
+    /// 内容的起始列，子列表与续行缩进到这一列
+    content_indent: usize,
+    /// 输出的编号，无序列表为 0
+    number: u64,
+    /// 输出的无序列表标记，有序列表为 `None`
+    bullet: Option<char>,
+}
+
+/// TOML 中是否有注释。`#` 也可以出现在字符串中（如 `color = "#fff"`），因此按词法判断
+fn toml_has_comment(text: &str) -> bool {
+    toml_parser::Source::new(text)
+        .lex()
+        .any(|token| token.kind() == toml_parser::lexer::TokenKind::Comment)
+}
+
+/// 去掉每行开头至多 `indent` 个空格
+fn strip_indent(text: &str, indent: usize) -> String {
+    text.split('\n')
+        .map(|line| {
+            let spaces = line.len() - line.trim_start_matches(' ').len();
+            &line[spaces.min(indent)..]
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
 }
 
 fn get_formatter_command(
@@ -98,52 +157,64 @@ impl<'a> Formatter<'a> {
             latex_args: Args::default(),
             latex_logs: Vec::new(),
             output: String::new(),
+            diagnostics: Vec::new(),
+            list_levels: Vec::new(),
+            words_around: (false, false),
+            code_block_count: 0,
         }
     }
     pub fn get_output(self) -> String {
         self.output
     }
 
-    fn format_chinese(&mut self, text: &str, prev_token: &Option<&Token>) {
-        if (self.config.space_between_zh_and_en && matches!(prev_token, Some(Token::English(_))))
-            || (self.config.space_between_zh_and_num
-                && matches!(prev_token, Some(Token::Number(_))))
-            || matches!(prev_token, Some(Token::InlineMath(_)))
-            || matches!(prev_token, Some(Token::InlineCode(_)))
-        {
-            self.output.push(' ');
-        }
-        self.output.push_str(text);
+    /// 取出格式化过程中产生的诊断信息，需在 `get_output` 之前调用
+    pub fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.diagnostics)
     }
 
-    fn format_english(&mut self, text: &str, prev_token: &Option<&Token>) {
-        if (self.config.space_between_zh_and_en && matches!(prev_token, Some(Token::Chinese(_))))
-            || matches!(prev_token, Some(Token::InlineMath(_)))
-            || matches!(prev_token, Some(Token::InlineCode(_)))
-        {
-            self.output.push(' ');
+    /// 相邻两个 token 之间是否需要加空格。`prev` 与 `next` 都是强调等容器边缘处的 token，
+    /// 因此 `中文**English**` 这样的边界与不带强调时的规则一致
+    fn needs_space(&self, prev: Option<&TokenKind>, next: &TokenKind) -> bool {
+        match (prev, next) {
+            (
+                Some(TokenKind::English(_) | TokenKind::Url(_) | TokenKind::Autolink(_)),
+                TokenKind::Chinese(_),
+            )
+            | (
+                Some(TokenKind::Chinese(_)),
+                TokenKind::English(_) | TokenKind::Url(_) | TokenKind::Autolink(_),
+            ) => self.config.space_between_zh_and_en,
+            (Some(TokenKind::Number(_)), TokenKind::Chinese(_))
+            | (Some(TokenKind::Chinese(_)), TokenKind::Number(_)) => {
+                self.config.space_between_zh_and_num
+            }
+            (
+                Some(TokenKind::InlineMath(_) | TokenKind::InlineCode(..)),
+                TokenKind::Chinese(_)
+                | TokenKind::English(_)
+                | TokenKind::Number(_)
+                | TokenKind::Url(_)
+                | TokenKind::Autolink(_),
+            )
+            | (
+                Some(TokenKind::Chinese(_) | TokenKind::English(_) | TokenKind::Number(_)),
+                TokenKind::InlineMath(_) | TokenKind::InlineCode(..),
+            ) => true,
+            _ => false,
         }
-        self.output.push_str(text);
     }
 
-    fn format_number(&mut self, text: &str, prev_token: &Option<&Token>) {
-        if (self.config.space_between_zh_and_num && matches!(prev_token, Some(Token::Chinese(_))))
-            || matches!(prev_token, Some(Token::InlineMath(_)))
-            || matches!(prev_token, Some(Token::InlineCode(_)))
-        {
+    /// 在 `next` 之前按需加空格后输出 `text`
+    fn push_spaced(&mut self, text: &str, next: &TokenKind, prev_token: &Option<&TokenKind>) {
+        if self.needs_space(*prev_token, next) {
             self.output.push(' ');
         }
         self.output.push_str(text);
     }
 
-    fn format_inline_math(&mut self, text: &str, prev_token: &Option<&Token>) {
-        if let Some(prev) = prev_token {
-            match prev {
-                Token::Chinese(_) | Token::English(_) | Token::Number(_) => {
-                    self.output.push(' ');
-                }
-                _ => {}
-            }
+    fn format_inline_math(&mut self, text: &str, prev_token: &Option<&TokenKind>) {
+        if self.needs_space(*prev_token, &TokenKind::InlineMath(text)) {
+            self.output.push(' ');
         }
         self.output.push('$');
         self.output.push_str(text);
@@ -156,7 +227,7 @@ impl<'a> Formatter<'a> {
         output.push_str("$$\n");
         output.push_str(&format_file(
             text,
-            Path::new("tex-fmt.log"),
+            Path::new(LATEX_LOG_LABEL),
             &self.latex_args,
             &mut self.latex_logs,
         ));
@@ -164,10 +235,49 @@ impl<'a> Formatter<'a> {
         self.ensure_empty_line();
     }
 
-    fn format_block_code_par(config: &Config, language: &str, content: &str) -> String {
+    /// 格式化单个代码块，可在线程池中并行调用。
+    /// 外部格式化工具失败时保留原内容，并返回对应的诊断信息。
+    fn format_block_code_par(
+        config: &Config,
+        index: usize,
+        code_block: &CodeBlock,
+    ) -> (String, Vec<Diagnostic>) {
+        let CodeBlock {
+            language,
+            tag,
+            attributes,
+            fence,
+            content,
+            indent,
+            ..
+        } = *code_block;
+        let dedented;
+        let content = if indent > 0 {
+            dedented = strip_indent(content, indent);
+            &dedented
+        } else {
+            content
+        };
+        let mut diagnostics = Vec::new();
         let output = &mut String::new();
-        output.push_str("```");
-        output.push_str(language);
+        output.push_str(fence);
+        let tag = match config.language_tag {
+            LanguageTag::Preserve => tag,
+            LanguageTag::Normalize => config
+                .language_tag_names
+                .get(language)
+                .map_or(language, String::as_str),
+        };
+        // 以围栏字符开头的信息字符串（如 `~~~ ~~~x`）紧贴围栏会改变围栏的长度，需保留空格
+        if fence
+            .chars()
+            .next()
+            .is_some_and(|c| tag.starts_with(c) || (tag.is_empty() && attributes.starts_with(c)))
+        {
+            output.push(' ');
+        }
+        output.push_str(tag);
+        output.push_str(attributes);
         if !content.starts_with('\n') {
             output.push('\n');
         }
@@ -175,30 +285,48 @@ impl<'a> Formatter<'a> {
             if language == "tex" {
                 output.push_str(&format_file(
                     content,
-                    Path::new("tex-fmt.log"),
+                    Path::new(LATEX_LOG_LABEL),
                     &Args::default(),
                     &mut Vec::new(),
                 ));
             } else if language == "md" {
-                output.push_str(&format_string(content, config));
-            } else {
-                if let Some(formatter) = config.code_formatters.get(language) {
-                    if let Some((cmd, args)) = get_formatter_command(formatter, language) {
-                        match format_with_command(&cmd, &args, content) {
-                            Ok(formatted) => {
-                                output.push_str(&formatted);
-                            }
-                            Err(e) => {
-                                eprintln!("Failed to format code block: {}", e);
-                                output.push_str(content);
-                            }
-                        }
-                    } else {
+                let (formatted, nested) = format_string(content, config);
+                output.push_str(formatted.trim_end_matches('\n'));
+                // 嵌套文档中的位置换算为在外层文档中的位置
+                let origin = &code_block.content_span;
+                diagnostics.extend(nested.into_iter().map(|mut diagnostic| {
+                    diagnostic.span = diagnostic
+                        .span
+                        .relative_to(origin.bytes.start, origin.start);
+                    diagnostic
+                }));
+            } else if let Some(formatter) = config.code_formatter(language)
+                && let Some((cmd, args)) = get_formatter_command(formatter, language)
+            {
+                match format_with_command(cmd, &args, content) {
+                    Ok(formatted) => {
+                        output.push_str(&formatted);
+                    }
+                    Err(failure) => {
                         output.push_str(content);
+                        diagnostics.push(Diagnostic {
+                            span: code_block.span.clone(),
+                            message: failure.message,
+                            kind: DiagnosticKind::ExternalFormatter {
+                                code_block_index: index,
+                                language: language.to_string(),
+                                command: std::iter::once(cmd)
+                                    .chain(args.iter().copied())
+                                    .map(str::to_string)
+                                    .collect(),
+                                exit_status: failure.exit_status,
+                                stderr: failure.stderr,
+                            },
+                        });
                     }
-                } else {
-                    output.push_str(content);
                 }
+            } else {
+                output.push_str(content);
             }
         } else {
             output.push_str(content);
@@ -206,69 +334,379 @@ impl<'a> Formatter<'a> {
         if !output.ends_with('\n') {
             output.push('\n');
         }
-        output.push_str("```");
-        std::mem::take(output)
+        output.push_str(fence);
+        (std::mem::take(output), diagnostics)
     }
 
-    fn format_block_code(&mut self, language: &str, content: &str) {
-        self.ensure_empty_line();
+    /// 默认原样保留 front matter；开启 `format_front_matter` 时格式化其内容，
+    /// 失败时保持原样并记录诊断信息。两端的分隔线始终保持原样。
+    /// TOML 经 `toml::Table` 重新序列化，键顺序、引号和内联表的写法都会改变
+    fn format_front_matter(&mut self, front_matter: &FrontMatter, span: &Span) {
+        if !self.config.format_front_matter {
+            self.output.push_str(front_matter.source);
+            return;
+        }
+        let formatted = match front_matter.kind {
+            FrontMatterKind::Yaml => {
+                match self
+                    .config
+                    .code_formatter("yml")
+                    .and_then(|formatter| get_formatter_command(formatter, "yml"))
+                {
+                    Some((cmd, args)) => format_with_command(cmd, &args, front_matter.content)
+                        .map_err(|failure| (failure.message, failure.stderr)),
+                    None => Ok(front_matter.content.to_string()),
+                }
+            }
+            // toml 只能在序列化时丢弃注释，含注释的内容不做修改
+            FrontMatterKind::Toml if toml_has_comment(front_matter.content) => {
+                Ok(front_matter.content.to_string())
+            }
+            FrontMatterKind::Toml => front_matter
+                .content
+                .parse::<toml::Table>()
+                .map_err(|e| e.to_string())
+                .and_then(|table| toml::to_string(&table).map_err(|e| e.to_string()))
+                .map_err(|e| {
+                    (
+                        format!("front matter 不是合法的 TOML: {}", e),
+                        String::new(),
+                    )
+                }),
+        };
+        let content = match formatted {
+            Ok(content) => content,
+            Err((message, stderr)) => {
+                self.diagnostics.push(Diagnostic {
+                    span: span.clone(),
+                    message,
+                    kind: DiagnosticKind::FrontMatter { stderr },
+                });
+                self.output.push_str(front_matter.source);
+                return;
+            }
+        };
+        let source = front_matter.source;
+        let opening = &source[..source.find('\n').unwrap_or(source.len())];
+        let closing = &source[source.rfind('\n').map_or(0, |i| i + 1)..];
         let output = &mut self.output;
-        output.push_str("```");
-        output.push_str(language);
-        if !content.starts_with('\n') {
+        output.push_str(opening);
+        output.push('\n');
+        output.push_str(content.trim_matches('\n'));
+        if !content.trim_matches('\n').is_empty() {
             output.push('\n');
         }
-        if self.config.format_code_block {
-            if language == "tex" {
-                output.push_str(&format_file(
-                    content,
-                    Path::new("tex-fmt.log"),
-                    &self.latex_args,
-                    &mut self.latex_logs,
-                ));
-            } else if language == "md" {
-                output.push_str(&format_string(content, self.config));
-            } else {
-                if let Some(formatter) = self.config.code_formatters.get(language) {
-                    if let Some((cmd, args)) = get_formatter_command(formatter, language) {
-                        match format_with_command(&cmd, &args, content) {
-                            Ok(formatted) => {
-                                output.push_str(&formatted);
-                            }
-                            Err(e) => {
-                                eprintln!("Failed to format code block: {}", e);
-                                output.push_str(content);
-                            }
+        output.push_str(closing);
+    }
+
+    fn format_inline_code(&mut self, text: &str, ticks: usize, prev_token: &Option<&TokenKind>) {
+        if self.needs_space(*prev_token, &TokenKind::InlineCode(text, ticks)) {
+            self.output.push(' ');
+        }
+        // 沿用原来的定界符，只有内容中出现同样长的一串 ` 时才加长；
+        // 内容以 ` 开头或结尾时两侧需加空格
+        let mut ticks = ticks;
+        while text.split(|c| c != '`').any(|run| run.len() == ticks) {
+            ticks += 1;
+        }
+        let ticks = "`".repeat(ticks);
+        let padding = if text.starts_with('`') || text.ends_with('`') {
+            " "
+        } else {
+            ""
+        };
+        let output = &mut self.output;
+        output.push_str(&ticks);
+        output.push_str(padding);
+        output.push_str(text);
+        output.push_str(padding);
+        output.push_str(&ticks);
+    }
+
+    /// 按配置统一列表标记与编号，并按嵌套层级重新缩进：子列表对齐到上一层列表项的内容。
+    /// 紧挨着的两个无序列表只靠标记区分，统一后相同时改用其他标记，以免合并为一个列表
+    fn format_list_item(&mut self, item: &ListItem) {
+        self.list_levels.truncate(item.level + 1);
+        let indent = self.list_indent(item.level);
+        let previous_bullet = self
+            .list_levels
+            .get(item.level)
+            .and_then(|previous| previous.bullet);
+        let (marker, number, bullet) = match item.marker {
+            ListMarker::Bullet(original) => {
+                let bullet = match self.config.bullet_list_marker {
+                    BulletListMarker::Preserve => original,
+                    BulletListMarker::Dash => '-',
+                    BulletListMarker::Asterisk => '*',
+                    BulletListMarker::Plus => '+',
+                };
+                let bullet = match previous_bullet {
+                    // 同一列表中的各项沿用第一项的标记
+                    Some(previous) if !item.starts_list => previous,
+                    Some(previous) if item.follows_list && previous == bullet => {
+                        if original != previous {
+                            original
+                        } else {
+                            ['-', '*', '+']
+                                .into_iter()
+                                .find(|&c| c != previous)
+                                .unwrap()
                         }
-                    } else {
-                        output.push_str(content);
                     }
-                } else {
-                    output.push_str(content);
-                }
+                    _ => bullet,
+                };
+                (bullet.to_string(), 0, Some(bullet))
+            }
+            ListMarker::Ordered { number, delimiter } => {
+                let number = match self.config.ordered_list_numbering {
+                    OrderedListNumbering::Preserve => number,
+                    OrderedListNumbering::One => 1,
+                    OrderedListNumbering::Increasing => match self.list_levels.get(item.level) {
+                        Some(previous) if !item.starts_list => previous.number + 1,
+                        _ => number,
+                    },
+                };
+                (format!("{}{}", number, delimiter), number, None)
             }
+        };
+
+        let level = ListLevel {
+            content_indent: indent + marker.len() + 1,
+            number,
+            bullet,
+        };
+        if item.level < self.list_levels.len() {
+            self.list_levels[item.level] = level;
         } else {
-            output.push_str(content);
+            self.list_levels.push(level);
         }
-        if !output.ends_with('\n') {
+
+        let output = &mut self.output;
+        output.extend(std::iter::repeat_n(' ', indent));
+        output.push_str(&marker);
+        if !item.empty {
+            output.push(' ');
+        }
+    }
+
+    /// 第 `level` 层列表项（从 0 开始）的标记所在的列
+    fn list_indent(&self, level: usize) -> usize {
+        level
+            .checked_sub(1)
+            .and_then(|parent| self.list_levels.get(parent).or(self.list_levels.last()))
+            .map_or(0, |parent| parent.content_indent)
+    }
+
+    /// 对齐表格的各列：列宽按显示宽度计算（中文等全角字符占两列），
+    /// 分隔行统一为 `---`、`:--`、`:-:`、`--:`，单元格内同样应用中英文空格规则。
+    /// 数据行的单元格多于表头时保持原样，避免丢失内容。
+    fn format_table(&mut self, table: &Table<'a>) {
+        let columns = table.alignments.len();
+        if table.rows.iter().any(|row| row.len() > columns) {
+            self.output.push_str(table.source);
+            return;
+        }
+        let rows: Vec<Vec<String>> = table
+            .rows
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|cell| {
+                        let mut cell_formatter = Formatter::new(self.config);
+                        cell_formatter.format(cell, &vec![]);
+                        cell_formatter.get_output()
+                    })
+                    .collect()
+            })
+            .collect();
+        // 分隔行中每列至少需要 3 个字符
+        let mut widths = vec![3; columns];
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.width());
+            }
+        }
+
+        let output = &mut self.output;
+        for (i, row) in rows.iter().enumerate() {
+            if i == 1 {
+                push_table_delimiter(output, &table.alignments, &widths);
+            }
+            output.push('|');
+            for (column, (&width, &alignment)) in widths.iter().zip(&table.alignments).enumerate() {
+                let cell = row.get(column).map_or("", String::as_str);
+                let padding = width - cell.width();
+                let (left, right) = match alignment {
+                    Alignment::Right => (padding, 0),
+                    Alignment::Center => (padding / 2, padding - padding / 2),
+                    Alignment::None | Alignment::Left => (0, padding),
+                };
+                output.push(' ');
+                output.extend(std::iter::repeat_n(' ', left));
+                output.push_str(cell);
+                output.extend(std::iter::repeat_n(' ', right));
+                output.push_str(" |");
+            }
             output.push('\n');
         }
-        output.push_str("```");
-        self.ensure_empty_line();
+        if rows.len() == 1 {
+            push_table_delimiter(output, &table.alignments, &widths);
+        }
+        output.pop();
+    }
+
+    /// 链接文本与图片的替代文本按正文规则格式化，目标与标题原样保留
+    fn format_link(&mut self, link: &Link<'a>) {
+        let mut text_formatter = Formatter::new(self.config);
+        text_formatter.format(&link.text, &vec![]);
+        let text = text_formatter.get_output();
+        let output = &mut self.output;
+        if link.image {
+            output.push('!');
+        }
+        output.push('[');
+        output.push_str(&text);
+        output.push(']');
+        output.push_str(link.target);
     }
 
-    fn format_inline_code(&mut self, text: &str, prev_token: &Option<&Token>) {
-        if let Some(prev) = prev_token {
-            match prev {
-                Token::Chinese(_) | Token::English(_) | Token::Number(_) => {
-                    self.output.push(' ');
+    /// 内容单独格式化；定界符按配置统一，并按内容首尾的 token 决定与前后文之间的空格。
+    /// 加空格之后仍紧挨着字母或数字的一侧不能使用 `_`，此时保持 `*`
+    fn format_emphasis(
+        &mut self,
+        emphasis: &Emphasis<'a>,
+        prev_token: &Option<&TokenKind>,
+        next_token: Option<&TokenKind>,
+        (first, last): (bool, bool),
+    ) {
+        let (Some(head), Some(tail)) = (emphasis.content.first(), emphasis.content.last()) else {
+            // 没有可以格式化的内容时保持原文
+            self.output.push_str(emphasis.source);
+            return;
+        };
+        let space_before = self.needs_space(*prev_token, edge_token(&head.kind, true));
+        let space_after = next_token.is_some_and(|next| {
+            self.needs_space(Some(edge_token(&tail.kind, false)), edge_token(next, true))
+        });
+        let word_before = (emphasis.word_before || first && self.words_around.0) && !space_before;
+        let word_after = (emphasis.word_after || last && self.words_around.1) && !space_after;
+        let intraword = word_before || word_after;
+        let delimiter = match emphasis.kind {
+            EmphasisKind::Emphasis => match self.config.emphasis_marker {
+                EmphasisMarker::Asterisk => "*",
+                EmphasisMarker::Underscore if !intraword => "_",
+                _ if emphasis.delimiter == '_' => "_",
+                _ => "*",
+            },
+            EmphasisKind::Strong => match self.config.strong_marker {
+                StrongMarker::Asterisk => "**",
+                StrongMarker::Underscore if !intraword => "__",
+                _ if emphasis.delimiter == '_' => "__",
+                _ => "**",
+            },
+            EmphasisKind::Strikethrough => "~~",
+        };
+        let mut content_formatter = Formatter::new(self.config);
+        content_formatter.words_around = (word_before, word_after);
+        content_formatter.format(&emphasis.content, &vec![]);
+        let content = content_formatter.get_output();
+        let output = &mut self.output;
+        if space_before {
+            output.push(' ');
+        }
+        output.push_str(delimiter);
+        output.push_str(&content);
+        output.push_str(delimiter);
+    }
+
+    /// HTML 块原样输出；开启 `format_details_text` 时，`<details>`、`<summary>` 开头的块中
+    /// 标签之间的文字按正文的规则加空格
+    fn format_html_block(&mut self, html: &str) {
+        let name = html
+            .trim_start()
+            .trim_start_matches('<')
+            .trim_start_matches('/');
+        let name = &name[..name
+            .find(|c: char| !c.is_ascii_alphanumeric())
+            .unwrap_or(name.len())];
+        if !self.config.format_details_text
+            || !(name.eq_ignore_ascii_case("details") || name.eq_ignore_ascii_case("summary"))
+        {
+            self.output.push_str(html);
+            return;
+        }
+        let mut text_start = 0;
+        let mut i = 0;
+        while let Some(offset) = html[i..].find('<') {
+            let tag_start = i + offset;
+            match html_tag_len(&html[tag_start..]) {
+                Some(len) => {
+                    self.format_html_text(&html[text_start..tag_start]);
+                    self.output.push_str(&html[tag_start..tag_start + len]);
+                    i = tag_start + len;
+                    text_start = i;
                 }
-                _ => {}
+                None => i = tag_start + 1,
+            }
+        }
+        self.format_html_text(&html[text_start..]);
+    }
+
+    /// 逐行格式化 HTML 标签之间的文字，各行首尾的空白保持不变
+    fn format_html_text(&mut self, text: &str) {
+        for (i, line) in text.split('\n').enumerate() {
+            if i > 0 {
+                self.output.push('\n');
+            }
+            let content = line.trim();
+            if content.is_empty() {
+                self.output.push_str(line);
+                continue;
+            }
+            let mut parser = Parser::inline(content);
+            parser.parse();
+            let mut text_formatter = Formatter::new(self.config);
+            text_formatter.format(parser.get_tokens(), parser.get_code_blocks());
+            let output = &mut self.output;
+            output.push_str(&line[..line.len() - line.trim_start().len()]);
+            output.push_str(&text_formatter.get_output());
+            output.push_str(&line[line.trim_end().len()..]);
+        }
+    }
+
+    /// 引用块的内容作为嵌套文档格式化后，每一行加上 `> ` 前缀。
+    /// 内容中的解析问题已由解析器记录，这里只收集格式化代码块等产生的诊断信息
+    fn format_block_quote(&mut self, quote: &BlockQuote) {
+        let Some(content) = quote.tokens() else {
+            self.output.push_str(quote.source);
+            return;
+        };
+        let mut content_formatter = Formatter::new(self.config);
+        content_formatter.code_block_count = self.code_block_count;
+        content_formatter.format(&content.tokens, &content.code_blocks);
+        self.code_block_count = content_formatter.code_block_count;
+        self.diagnostics
+            .extend(
+                content_formatter
+                    .take_diagnostics()
+                    .into_iter()
+                    .map(|mut diagnostic| {
+                        diagnostic.span = quote.source_span(&diagnostic.span);
+                        diagnostic
+                    }),
+            );
+        let content = content_formatter.get_output();
+        let output = &mut self.output;
+        for (i, line) in content.trim_matches('\n').split('\n').enumerate() {
+            if i > 0 {
+                output.push('\n');
+            }
+            output.push('>');
+            if !line.is_empty() {
+                output.push(' ');
+                output.push_str(line);
             }
         }
-        self.output.push('`');
-        self.output.push_str(text);
-        self.output.push('`');
     }
 
     fn format_title(&mut self, title_tokens: &Vec<Token<'a>>, level: &usize) {
@@ -286,46 +724,102 @@ impl<'a> Formatter<'a> {
 
     pub fn format(&mut self, tokens: &Vec<Token<'a>>, code_blocks: &Vec<CodeBlock>) {
         self.output.reserve(tokens.len() * 3);
-        let mut prev_token: Option<&Token> = None;
-        let code_block_formatted_strings = code_blocks
-            .par_iter()
-            .map(|code_block| {
-                Formatter::format_block_code_par(
-                    self.config,
-                    code_block.language,
-                    code_block.content,
-                )
-            })
-            .collect::<Vec<String>>();
+        let mut prev_token: Option<&TokenKind> = None;
+        let (code_block_formatted_strings, diagnostics): (Vec<String>, Vec<Vec<Diagnostic>>) =
+            code_blocks
+                .par_iter()
+                .enumerate()
+                .map(|(index, code_block)| {
+                    Formatter::format_block_code_par(self.config, index, code_block)
+                })
+                .unzip();
+        let mut diagnostics = diagnostics.into_iter();
         let mut code_block_id = 0;
 
-        for token in tokens.iter() {
-            match token {
-                Token::Chinese(text) => self.format_chinese(text, &prev_token),
-                Token::English(text) => self.format_english(text, &prev_token),
-                Token::Number(text) => self.format_number(text, &prev_token),
-                Token::InlineMath(text) => self.format_inline_math(text, &prev_token),
-                Token::BlockMath(text) => self.format_block_math(text),
-                Token::FakeCodeBlock => {
-                    self.ensure_empty_line();
+        for (i, token) in tokens.iter().enumerate() {
+            match &token.kind {
+                kind @ (TokenKind::Chinese(text)
+                | TokenKind::English(text)
+                | TokenKind::Number(text)
+                // 裸链接按英文单词处理，与中文之间同样加空格，但其内部保持不变
+                | TokenKind::Url(text)
+                | TokenKind::Autolink(text)) => self.push_spaced(text, kind, &prev_token),
+                TokenKind::InlineMath(text) => self.format_inline_math(text, &prev_token),
+                TokenKind::BlockMath(text) => self.format_block_math(text),
+                TokenKind::FakeCodeBlock => {
                     let formatted = &code_block_formatted_strings[code_block_id];
-                    self.output.push_str(formatted);
                     code_block_id += 1;
-                    self.ensure_empty_line();
+                    // 并行格式化时只知道代码块在本层中的序号，这里换成在整个文档中的序号
+                    for mut diagnostic in diagnostics.next().unwrap_or_default() {
+                        if let DiagnosticKind::ExternalFormatter {
+                            code_block_index, ..
+                        } = &mut diagnostic.kind
+                        {
+                            *code_block_index = self.code_block_count;
+                        }
+                        self.diagnostics.push(diagnostic);
+                    }
+                    self.code_block_count += 1;
+                    if i > 0
+                        && matches!(
+                            tokens[i - 1].kind,
+                            TokenKind::ListItem(_) | TokenKind::ListIndent(..)
+                        )
+                    {
+                        // 列表项中的代码块接在标记或缩进之后，其余各行缩进到围栏所在的列
+                        let column =
+                            self.output.len() - self.output.rfind('\n').map_or(0, |i| i + 1);
+                        for (n, line) in formatted.split('\n').enumerate() {
+                            if n > 0 {
+                                self.output.push('\n');
+                                if !line.is_empty() {
+                                    self.output.extend(std::iter::repeat_n(' ', column));
+                                }
+                            }
+                            self.output.push_str(line);
+                        }
+                    } else {
+                        self.ensure_empty_line();
+                        self.output.push_str(formatted);
+                        self.ensure_empty_line();
+                    }
                 }
-                Token::CodeBlock { language, content } => self.format_block_code(language, content),
-                Token::InlineCode(text) => self.format_inline_code(text, &prev_token),
-                Token::NewLine => {
+                TokenKind::InlineCode(text, ticks) => {
+                    self.format_inline_code(text, *ticks, &prev_token)
+                }
+                TokenKind::NewLine => {
                     if !self.output.ends_with("\n\n") {
                         self.output.push('\n');
                     }
                 }
-                Token::Text(text) => {
+                TokenKind::Text(text) => {
                     self.output.push_str(text);
                 }
-                Token::Title(title_tokens, level) => self.format_title(title_tokens, level),
+                TokenKind::Title(title_tokens, level) => self.format_title(title_tokens, level),
+                TokenKind::ListItem(item) => self.format_list_item(item),
+                TokenKind::Table(table) => self.format_table(table),
+                TokenKind::Link(link) => self.format_link(link),
+                TokenKind::BlockQuote(quote) => self.format_block_quote(quote),
+                TokenKind::FrontMatter(front_matter) => {
+                    self.format_front_matter(front_matter, &token.span)
+                }
+                TokenKind::Emphasis(emphasis) => self.format_emphasis(
+                    emphasis,
+                    &prev_token,
+                    tokens.get(i + 1).map(|next| &next.kind),
+                    (i == 0, i + 1 == tokens.len()),
+                ),
+                TokenKind::LinkDefinition(text)
+                | TokenKind::Ignored(text)
+                | TokenKind::InlineHtml(text)
+                | TokenKind::IndentedCode(text) => self.output.push_str(text),
+                TokenKind::HtmlBlock(html) => self.format_html_block(html),
+                TokenKind::ListIndent(level, extra) => {
+                    let indent = self.list_indent(*level) + extra;
+                    self.output.extend(std::iter::repeat_n(' ', indent));
+                }
             }
-            prev_token = Some(token);
+            prev_token = Some(edge_token(&token.kind, false));
         }
     }
 
@@ -341,39 +835,421 @@ impl<'a> Formatter<'a> {
     }
 }
 
-fn format_with_command(cmd: &str, args: &[&'static str], content: &str) -> Result<String> {
-    #[cfg(debug_assertions)]
-    {
-        eprintln!("[DEBUG] Try to run external formatter: {} {:?}", cmd, args);
+/// 输出表格的分隔行（以换行结尾）
+fn push_table_delimiter(output: &mut String, alignments: &[Alignment], widths: &[usize]) {
+    output.push('|');
+    for (&width, alignment) in widths.iter().zip(alignments) {
+        let (left, right) = match alignment {
+            Alignment::None => ("", ""),
+            Alignment::Left => (":", ""),
+            Alignment::Center => (":", ":"),
+            Alignment::Right => ("", ":"),
+        };
+        output.push(' ');
+        output.push_str(left);
+        output.extend(std::iter::repeat_n('-', width - left.len() - right.len()));
+        output.push_str(right);
+        output.push_str(" |");
+    }
+    output.push('\n');
+}
+
+/// 外部格式化工具的失败信息
+struct CommandFailure {
+    message: String,
+    exit_status: Option<i32>,
+    stderr: String,
+}
+
+impl CommandFailure {
+    fn new(message: String) -> Self {
+        CommandFailure {
+            message,
+            exit_status: None,
+            stderr: String::new(),
+        }
     }
+}
 
+fn format_with_command(
+    cmd: &str,
+    args: &[&'static str],
+    content: &str,
+) -> Result<String, CommandFailure> {
     let mut child = match Command::new(cmd)
         .args(args)
         .stdin(std::process::Stdio::piped())
         .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
         .spawn()
     {
         Ok(child) => child,
         Err(e) => {
             if let Some(2) = e.raw_os_error() {
                 // os error 2: No such file or directory
-                return Err(anyhow!("未找到格式化工具 `{}`。", cmd));
+                return Err(CommandFailure::new(format!("未找到格式化工具 `{}`。", cmd)));
             } else {
-                return Err(anyhow!("无法启动格式化工具 `{}`: {}", cmd, e));
+                return Err(CommandFailure::new(format!(
+                    "无法启动格式化工具 `{}`: {}",
+                    cmd, e
+                )));
             }
         }
     };
 
     if let Some(mut stdin) = child.stdin.take() {
         use std::io::Write;
-        stdin.write_all(content.as_bytes())?;
+        // 工具可能不读完 stdin 就退出，写入失败时以其退出状态为准
+        let _ = stdin.write_all(content.as_bytes());
     }
 
-    let output = child.wait_with_output()?;
-    if output.status.success() {
-        Ok(String::from_utf8(output.stdout)?)
-    } else {
-        let error_message = String::from_utf8_lossy(&output.stderr);
-        Err(anyhow!("{error_message}"))
+    let output = child
+        .wait_with_output()
+        .map_err(|e| CommandFailure::new(format!("等待格式化工具 `{}` 时出错: {}", cmd, e)))?;
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    if !output.status.success() {
+        return Err(CommandFailure {
+            message: format!("格式化工具 `{}` 运行失败 ({})", cmd, output.status),
+            exit_status: output.status.code(),
+            stderr,
+        });
+    }
+    String::from_utf8(output.stdout).map_err(|_| CommandFailure {
+        message: format!("格式化工具 `{}` 的输出不是合法的 UTF-8", cmd),
+        exit_status: output.status.code(),
+        stderr,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(input: &str) -> String {
+        format_string(input, &Config::default()).0
+    }
+
+    #[test]
+    fn list_continuation_keeps_extra_indent() {
+        let input = "- a\n\n  cont\n\n      code\n";
+        assert_eq!(format(input), input);
+        assert_eq!(format("* a\n\n   cont\n"), "* a\n\n   cont\n");
+    }
+
+    #[test]
+    fn adjacent_bullet_lists_stay_separate() {
+        let config = Config {
+            bullet_list_marker: BulletListMarker::Dash,
+            ..Config::default()
+        };
+        let format = |input| format_string(input, &config).0;
+        assert_eq!(format("- a\n- b\n* c\n* d\n"), "- a\n- b\n* c\n* d\n");
+        assert_eq!(format("* a\n- b\n"), "- a\n* b\n");
+        assert_eq!(format("* a\n\ntext\n\n* b\n"), "- a\n\ntext\n\n- b\n");
+    }
+
+    #[test]
+    fn numbers_after_paragraph_are_not_renumbered() {
+        let config = Config {
+            ordered_list_numbering: OrderedListNumbering::One,
+            ..Config::default()
+        };
+        for input in ["今年是\n2024. 很好的一年\n", "版本号\n3. 不是列表\n"] {
+            assert_eq!(format_string(input, &config).0, input);
+        }
+    }
+
+    #[test]
+    fn list_markers_are_preserved_by_default() {
+        let input = "* a\n* b\n\n3. c\n5. d\n";
+        assert_eq!(format(input), input);
+    }
+
+    #[test]
+    fn code_block_in_list_item_keeps_indent() {
+        let input = "- item\n\n  ```text\n  fn a(){}\n\n    b\n  ```\n- next\n";
+        assert_eq!(format(input), input);
+        assert_eq!(
+            format("1. ```md\n   中文English\n   ```\n"),
+            "1. ```md\n   中文 English\n   ```\n"
+        );
+    }
+
+    #[test]
+    fn inline_code_keeps_its_delimiter() {
+        for input in [
+            "Use ` carefully, and ``code`` here.\n",
+            "``a`b`` 与 `c`\n",
+            "``` `` ```\n",
+        ] {
+            let output = format(input);
+            assert_eq!(output, input);
+            assert_eq!(format(&output), output);
+        }
+        assert_eq!(format("中文`code`\n"), "中文 `code`\n");
+    }
+
+    #[test]
+    fn info_string_starting_with_tildes_stays_apart_from_fence() {
+        let input = "~~~ ~~~x\ncode\n~~~\n\n后文\n";
+        assert_eq!(format(input), input);
+        assert_eq!(
+            format("~~~  ~x\ncode\n~~~\n\na\n"),
+            "~~~ ~x\ncode\n~~~\n\na\n"
+        );
+        assert_eq!(
+            format("``` text\ncode\n```\n\na\n"),
+            "```text\ncode\n```\n\na\n"
+        );
+    }
+
+    #[test]
+    fn table_columns_align_by_display_width() {
+        assert_eq!(
+            format("| 名称 | b |\n|:---:|--:|\n| 中文 | 1 |\n| x | 22 |\n"),
+            "| 名称 |   b |\n| :--: | --: |\n| 中文 |   1 |\n|  x   |  22 |\n"
+        );
+    }
+
+    #[test]
+    fn table_cells_keep_escaped_pipes() {
+        assert_eq!(
+            format("| a\\|b | c |\n|-|-|\n| x | y |\n"),
+            "| a\\|b | c   |\n| ---- | --- |\n| x    | y   |\n"
+        );
+    }
+
+    #[test]
+    fn table_with_extra_cells_is_kept() {
+        let input = "| a | b |\n|---|---|\n| 1 | 2 | 3 |\n";
+        assert_eq!(format(input), input);
+    }
+
+    fn format_front_matter(input: &str) -> String {
+        let config = Config {
+            format_front_matter: true,
+            ..Config::default()
+        };
+        format_string(input, &config).0
+    }
+
+    #[test]
+    fn front_matter_is_preserved_by_default() {
+        let input = "+++\nb=1\na='x'\n+++\n\n正文\n";
+        assert_eq!(format(input), input);
+        let input = "---\ntitle:   标题\n---\n\n正文\n";
+        assert_eq!(format(input), input);
+    }
+
+    #[test]
+    fn toml_front_matter_is_rewritten() {
+        assert_eq!(
+            format_front_matter("+++\nb=1\ncolor='#fff'\np={x=1}\n+++\n\n正文\n"),
+            "+++\nb = 1\ncolor = \"#fff\"\n\n[p]\nx = 1\n+++\n\n正文\n"
+        );
+    }
+
+    #[test]
+    fn toml_front_matter_with_comments_is_kept() {
+        let input = "+++\nb = 1 # 注释\na = 2\n+++\n\n正文\n";
+        assert_eq!(format_front_matter(input), input);
+    }
+
+    #[test]
+    fn invalid_toml_front_matter_is_reported() {
+        let input = "+++\na = \n+++\n\n正文\n";
+        let config = Config {
+            format_front_matter: true,
+            ..Config::default()
+        };
+        let (output, diagnostics) = format_string(input, &config);
+        assert_eq!(output, input);
+        assert!(matches!(
+            diagnostics[..],
+            [Diagnostic {
+                kind: DiagnosticKind::FrontMatter { .. },
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn yaml_front_matter_without_formatter_is_kept() {
+        let config = Config {
+            format_front_matter: true,
+            code_formatters: Default::default(),
+            extend_code_formatters: false,
+            ..Config::default()
+        };
+        let input = "---\ntitle:   标题\n---\n\n正文\n";
+        assert_eq!(format_string(input, &config).0, input);
+    }
+
+    #[test]
+    fn emphasis_markers_are_preserved_by_default() {
+        assert_eq!(format("__a__ _b_ **c** *d*\n"), "__a__ _b_ **c** *d*\n");
+    }
+
+    fn format_with_markers(input: &str, emphasis: EmphasisMarker, strong: StrongMarker) -> String {
+        let config = Config {
+            emphasis_marker: emphasis,
+            strong_marker: strong,
+            ..Config::default()
+        };
+        format_string(input, &config).0
+    }
+
+    #[test]
+    fn emphasis_markers_are_normalized() {
+        let underscore = |input| {
+            format_with_markers(input, EmphasisMarker::Underscore, StrongMarker::Underscore)
+        };
+        let asterisk =
+            |input| format_with_markers(input, EmphasisMarker::Asterisk, StrongMarker::Asterisk);
+        assert_eq!(underscore("*a* **b** ***c***\n"), "_a_ __b__ ___c___\n");
+        assert_eq!(asterisk("_a_ __b__ ___c___\n"), "*a* **b** ***c***\n");
+        // 单词内部只能使用 `*`，`_` 则不会被识别为强调
+        assert_eq!(
+            underscore("foo*bar*baz foo**bar**\n"),
+            "foo*bar*baz foo**bar**\n"
+        );
+        assert_eq!(asterisk("snake_case_name\n"), "snake_case_name\n");
+        let output = underscore("中文*强调*English\n");
+        assert_eq!(output, "中文*强调* English\n");
+        assert_eq!(underscore(&output), output);
+    }
+
+    #[test]
+    fn emphasis_content_is_spaced() {
+        assert_eq!(format("中文**English**中文\n"), "中文 **English** 中文\n");
+        assert_eq!(format("~~删除English~~\n"), "~~删除 English~~\n");
+    }
+
+    #[test]
+    fn strikethrough_at_line_start_is_not_a_fence() {
+        let input = "~~strike~~ 中文\n\n~~~\ncode\n~~~\n\na\n";
+        assert_eq!(format(input), input);
+    }
+
+    #[test]
+    fn empty_emphasis_keeps_its_source() {
+        let config = Config::default();
+        let mut formatter = Formatter::new(&config);
+        let emphasis = Emphasis {
+            kind: EmphasisKind::Strong,
+            delimiter: '*',
+            word_before: false,
+            word_after: false,
+            content: Vec::new(),
+            source: "****",
+        };
+        formatter.format_emphasis(&emphasis, &None, None, (true, true));
+        assert_eq!(formatter.get_output(), "****");
+    }
+
+    #[test]
+    fn deep_block_quote_is_kept_verbatim() {
+        let input = "> ".repeat(3000) + "x\n";
+        assert_eq!(format(&input), input);
+        let input = ">".repeat(3000) + "\n";
+        let output = format(&input);
+        assert_eq!(format(&output), output);
+    }
+
+    #[test]
+    fn ignored_region_is_kept() {
+        assert_eq!(
+            format(
+                "a\n<!-- rustdown-ignore-start -->\n中文english  *x*\n<!-- rustdown-ignore-end -->\n中文english\n"
+            ),
+            "a\n<!-- rustdown-ignore-start -->\n中文english  *x*\n<!-- rustdown-ignore-end -->\n中文 english\n"
+        );
+        assert_eq!(
+            format("<!-- rustdown-ignore-next-line -->\n中文english\n中文english\n"),
+            "<!-- rustdown-ignore-next-line -->\n中文english\n中文 english\n"
+        );
+    }
+
+    #[test]
+    fn unterminated_ignore_start_runs_to_end() {
+        let input = "中文 a\n\n<!-- rustdown-ignore-start -->\n中文english\n\n- 中文english\n";
+        assert_eq!(format(input), input);
+    }
+
+    #[test]
+    fn ignore_directives_in_lists_and_quotes() {
+        assert_eq!(
+            format("- 中文a\n  <!-- rustdown-ignore-next-line -->\n  中文a\n- 中文a\n"),
+            "- 中文 a\n  <!-- rustdown-ignore-next-line -->\n  中文a\n- 中文 a\n"
+        );
+        assert_eq!(
+            format(
+                "- 中文a\n\n  <!-- rustdown-ignore-start -->\n  中文a\n  <!-- rustdown-ignore-end -->\n- 中文a\n"
+            ),
+            "- 中文 a\n\n  <!-- rustdown-ignore-start -->\n  中文a\n  <!-- rustdown-ignore-end -->\n- 中文 a\n"
+        );
+        assert_eq!(
+            format(
+                "> 中文a\n> <!-- rustdown-ignore-start -->\n> 中文a\n> <!-- rustdown-ignore-end -->\n> 中文a\n"
+            ),
+            "> 中文 a\n> <!-- rustdown-ignore-start -->\n> 中文a\n> <!-- rustdown-ignore-end -->\n> 中文 a\n"
+        );
+    }
+
+    #[test]
+    fn code_block_in_ignored_region_is_not_formatted() {
+        // 代码不合法，交给 rustfmt（或找不到 rustfmt）都会产生诊断信息
+        for input in [
+            "<!-- rustdown-ignore-start -->\n```rust\nfn main( {\n```\n<!-- rustdown-ignore-end -->\n\na\n",
+            "<!-- rustdown-ignore-next-line -->\n```rust\nfn main( {\n```\n\na\n",
+        ] {
+            let (output, diagnostics) = format_string(input, &Config::default());
+            assert_eq!(output, input);
+            assert!(diagnostics.is_empty());
+        }
+    }
+
+    #[test]
+    fn html_is_kept_verbatim() {
+        for input in [
+            "<div>\n中文a  *x*\n</div>\n\n中文 a\n",
+            "<!--\n中文a\n\n中文a\n-->\n中文 a\n",
+            "按 <kbd>Ctrl</kbd>键和<kbd>C</kbd>复制\n",
+            "中文<!-- 注释 a  b -->english\n",
+        ] {
+            assert_eq!(format(input), input);
+        }
+    }
+
+    #[test]
+    fn html_block_ends_at_blank_line() {
+        assert_eq!(
+            format("<div>\n中文a\n\n中文a\n"),
+            "<div>\n中文a\n\n中文 a\n"
+        );
+        assert_eq!(
+            format("<details>\n<summary>标题a</summary>\n\n中文a\n\n</details>\n"),
+            "<details>\n<summary>标题a</summary>\n\n中文 a\n\n</details>\n"
+        );
+    }
+
+    #[test]
+    fn code_blocks_are_numbered_per_document() {
+        // 代码不合法，交给 rustfmt（或找不到 rustfmt）都会产生诊断信息
+        let block = "```rust\nfn main( {\n```\n";
+        let quoted = "> ```rust\n> fn main( {\n> ```\n";
+        let input =
+            format!("{block}\n{quoted}\n> > ```rust\n> > fn main( {{\n> > ```\n\n{block}\na\n");
+        let (_, diagnostics) = format_string(&input, &Config::default());
+        let indices: Vec<_> = diagnostics
+            .iter()
+            .filter_map(|diagnostic| match diagnostic.kind {
+                DiagnosticKind::ExternalFormatter {
+                    code_block_index, ..
+                } => Some(code_block_index),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(indices, [0, 1, 2, 3]);
     }
 }
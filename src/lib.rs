@@ -1,7 +1,22 @@
 mod config;
+mod diagnostic;
+mod edit;
+mod error;
 mod parser;
 mod formatter;
 mod pipeline;
-pub use config::Config;
+mod span;
+pub use config::{
+    BulletListMarker, CONFIG_FILENAMES, Config, ConfigLayer, EmphasisMarker, LanguageTag,
+    OrderedListNumbering, StrongMarker, find_config_file,
+};
+pub use diagnostic::{Diagnostic, DiagnosticKind};
+pub use edit::{TextEdit, text_edits};
+pub use error::Error;
 pub use formatter::Formatter;
-pub use parser::Parser;
\ No newline at end of file
+pub use parser::{
+    Alignment, BlockQuote, CodeBlock, Emphasis, EmphasisKind, FrontMatter, FrontMatterKind, Link,
+    ListItem, ListMarker, Parser, Table, Token, TokenKind,
+};
+pub use pipeline::{Formatted, diagnose, format, format_range};
+pub use span::{Position, Span};
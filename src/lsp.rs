@@ -0,0 +1,407 @@
+//! `rustdown-formatter lsp`：通过 stdio 提供 Language Server Protocol 服务，
+//! 支持整篇、选区与输入时格式化，并发布未闭合代码块等诊断信息
+
+use crate::resolver::ConfigResolver;
+use anyhow::{Context, Result, anyhow};
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, RequestId, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, DidSaveTextDocument,
+    Notification as _, PublishDiagnostics,
+};
+use lsp_types::request::{Formatting, OnTypeFormatting, RangeFormatting, Request as _};
+use lsp_types::{self as lsp, OneOf, Url};
+use rustdown_formatter::{
+    CONFIG_FILENAMES, Config, ConfigLayer, Diagnostic, Position, Span, diagnose, format,
+    format_range, text_edits,
+};
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+struct Server {
+    connection: Connection,
+    /// 客户端在 initializationOptions 中传入的配置，优先级高于配置文件
+    overrides: ConfigLayer,
+    resolver: ConfigResolver,
+    /// 已打开的文档
+    documents: HashMap<Url, String>,
+}
+
+pub fn run() -> Result<()> {
+    let (connection, io_threads) = Connection::stdio();
+    let capabilities = lsp::ServerCapabilities {
+        text_document_sync: Some(lsp::TextDocumentSyncCapability::Options(
+            lsp::TextDocumentSyncOptions {
+                open_close: Some(true),
+                change: Some(lsp::TextDocumentSyncKind::FULL),
+                // 配置文件保存后需要重新读取
+                save: Some(lsp::TextDocumentSyncSaveOptions::Supported(true)),
+                ..Default::default()
+            },
+        )),
+        document_formatting_provider: Some(OneOf::Left(true)),
+        document_range_formatting_provider: Some(OneOf::Left(true)),
+        document_on_type_formatting_provider: Some(lsp::DocumentOnTypeFormattingOptions {
+            first_trigger_character: "\n".to_string(),
+            more_trigger_character: None,
+        }),
+        ..Default::default()
+    };
+    let params = connection.initialize(serde_json::to_value(capabilities)?)?;
+    let params: lsp::InitializeParams = serde_json::from_value(params)?;
+    let overrides = match params.initialization_options {
+        Some(options) if !options.is_null() => ConfigLayer::from_json_str(&options.to_string())
+            .context("initializationOptions 中的配置无效")?,
+        _ => ConfigLayer::default(),
+    };
+
+    let mut server = Server {
+        connection,
+        resolver: ConfigResolver::new(None, overrides.clone())?,
+        overrides,
+        documents: HashMap::new(),
+    };
+    server.main_loop()?;
+    drop(server);
+    io_threads.join()?;
+    Ok(())
+}
+
+impl Server {
+    fn main_loop(&mut self) -> Result<()> {
+        while let Ok(message) = self.connection.receiver.recv() {
+            match message {
+                Message::Request(request) => {
+                    if self.connection.handle_shutdown(&request)? {
+                        return Ok(());
+                    }
+                    let response = self.handle_request(request);
+                    self.connection.sender.send(Message::Response(response))?;
+                }
+                Message::Notification(notification) => {
+                    let method = notification.method.clone();
+                    // 单个通知无效（如参数格式不对）时只记录下来，连接断开才结束服务
+                    if let Err(e) = self.handle_notification(notification) {
+                        if e.is::<Disconnected>() {
+                            return Err(e);
+                        }
+                        eprintln!("处理 {} 通知失败: {:#}", method, e);
+                    }
+                }
+                Message::Response(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_request(&mut self, request: Request) -> Response {
+        let Request { id, method, params } = request;
+        let result = match method.as_str() {
+            Formatting::METHOD => parse_params(params).and_then(|params| self.formatting(params)),
+            RangeFormatting::METHOD => {
+                parse_params(params).and_then(|params| self.range_formatting(params))
+            }
+            OnTypeFormatting::METHOD => {
+                parse_params(params).and_then(|params| self.on_type_formatting(params))
+            }
+            _ => {
+                return Response::new_err(
+                    id,
+                    ErrorCode::MethodNotFound as i32,
+                    format!("不支持的请求 {}", method),
+                );
+            }
+        };
+        respond(id, result)
+    }
+
+    fn handle_notification(&mut self, notification: Notification) -> Result<()> {
+        let Notification { method, params } = notification;
+        match method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: lsp::DidOpenTextDocumentParams = parse_params(params)?;
+                let document = params.text_document;
+                self.documents.insert(document.uri.clone(), document.text);
+                self.publish(&document.uri, None)?;
+            }
+            DidChangeTextDocument::METHOD => {
+                let params: lsp::DidChangeTextDocumentParams = parse_params(params)?;
+                // 以全量方式同步，最后一项即为完整的新内容
+                if let Some(change) = params.content_changes.into_iter().last() {
+                    let uri = params.text_document.uri;
+                    self.documents.insert(uri.clone(), change.text);
+                    self.publish(&uri, None)?;
+                }
+            }
+            DidCloseTextDocument::METHOD => {
+                let params: lsp::DidCloseTextDocumentParams = parse_params(params)?;
+                let uri = params.text_document.uri;
+                self.documents.remove(&uri);
+                self.send_diagnostics(uri, Vec::new())?;
+            }
+            DidSaveTextDocument::METHOD => {
+                let params: lsp::DidSaveTextDocumentParams = parse_params(params)?;
+                let is_config = params
+                    .text_document
+                    .uri
+                    .path_segments()
+                    .and_then(|mut segments| segments.next_back())
+                    .is_some_and(|name| CONFIG_FILENAMES.contains(&name));
+                if is_config {
+                    self.resolver = ConfigResolver::new(None, self.overrides.clone())?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn formatting(&mut self, params: lsp::DocumentFormattingParams) -> Result<Vec<lsp::TextEdit>> {
+        let uri = params.text_document.uri;
+        let config = self.config(&uri)?;
+        let text = self.document(&uri)?;
+        let formatted = format(text, &config)?;
+        let edits = to_lsp_edits(text, &formatted.output);
+        self.publish(&uri, Some(&formatted.diagnostics))?;
+        Ok(edits)
+    }
+
+    fn range_formatting(
+        &mut self,
+        params: lsp::DocumentRangeFormattingParams,
+    ) -> Result<Vec<lsp::TextEdit>> {
+        let uri = params.text_document.uri;
+        let config = self.config(&uri)?;
+        let text = self.document(&uri)?;
+        let range = offset_at(text, params.range.start)..offset_at(text, params.range.end);
+        let formatted = format_range(text, range, &config)?;
+        Ok(to_lsp_edits(text, &formatted.output))
+    }
+
+    /// 换行时格式化上一行所在的段落
+    fn on_type_formatting(
+        &mut self,
+        params: lsp::DocumentOnTypeFormattingParams,
+    ) -> Result<Vec<lsp::TextEdit>> {
+        let uri = params.text_document_position.text_document.uri;
+        let config = self.config(&uri)?;
+        let text = self.document(&uri)?;
+        let line = params.text_document_position.position.line;
+        let line_start = offset_at(text, lsp::Position::new(line, 0));
+        let previous_line_end = text[..line_start]
+            .strip_suffix('\n')
+            .map_or(line_start, |s| s.strip_suffix('\r').unwrap_or(s).len());
+        // 正在输入的代码块或公式块尚未闭合，此时格式化会把文档剩余部分都当作它的内容
+        if diagnose(text)
+            .iter()
+            .any(|diagnostic| diagnostic.span.bytes.start <= previous_line_end)
+        {
+            return Ok(Vec::new());
+        }
+        let formatted = format_range(text, previous_line_end..previous_line_end, &config)?;
+        Ok(to_lsp_edits(text, &formatted.output))
+    }
+
+    fn config(&mut self, uri: &Url) -> Result<Arc<Config>> {
+        match uri.to_file_path() {
+            Ok(path) => self.resolver.resolve_file(&path),
+            // 未保存的文档等没有路径，从当前目录开始查找配置
+            Err(()) => self.resolver.resolve_dir(Path::new(".")),
+        }
+    }
+
+    fn document(&self, uri: &Url) -> Result<&String> {
+        self.documents
+            .get(uri)
+            .ok_or_else(|| anyhow!("文档 {} 没有打开", uri))
+    }
+
+    /// 发布文档的诊断信息；`diagnostics` 为 `None` 时只检查结构问题
+    fn publish(&self, uri: &Url, diagnostics: Option<&[Diagnostic]>) -> Result<()> {
+        let text = self.document(uri)?;
+        let diagnostics = match diagnostics {
+            Some(diagnostics) => diagnostics.to_vec(),
+            None => diagnose(text),
+        };
+        let lines: Vec<&str> = text.split('\n').collect();
+        let diagnostics = diagnostics
+            .iter()
+            .map(|diagnostic| lsp::Diagnostic {
+                range: to_lsp_range(&lines, &diagnostic.span),
+                severity: Some(lsp::DiagnosticSeverity::WARNING),
+                source: Some("rustdown-formatter".to_string()),
+                message: diagnostic.message.clone(),
+                ..Default::default()
+            })
+            .collect();
+        self.send_diagnostics(uri.clone(), diagnostics)
+    }
+
+    fn send_diagnostics(&self, uri: Url, diagnostics: Vec<lsp::Diagnostic>) -> Result<()> {
+        let params = lsp::PublishDiagnosticsParams::new(uri, diagnostics, None);
+        let notification = Notification::new(PublishDiagnostics::METHOD.to_string(), params);
+        self.connection
+            .sender
+            .send(Message::Notification(notification))
+            .map_err(|_| Disconnected)?;
+        Ok(())
+    }
+}
+
+/// 向客户端发送消息失败，说明连接已断开
+#[derive(Debug)]
+struct Disconnected;
+
+impl std::fmt::Display for Disconnected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "与客户端的连接已断开")
+    }
+}
+
+impl std::error::Error for Disconnected {}
+
+fn parse_params<P: DeserializeOwned>(params: serde_json::Value) -> Result<P> {
+    serde_json::from_value(params).context("请求参数无效")
+}
+
+fn respond(id: RequestId, result: Result<Vec<lsp::TextEdit>>) -> Response {
+    match result {
+        Ok(edits) => Response::new_ok(id, edits),
+        Err(e) => Response::new_err(id, ErrorCode::RequestFailed as i32, format!("{:#}", e)),
+    }
+}
+
+fn to_lsp_edits(input: &str, output: &str) -> Vec<lsp::TextEdit> {
+    let lines: Vec<&str> = input.split('\n').collect();
+    text_edits(input, output)
+        .into_iter()
+        .map(|edit| lsp::TextEdit::new(to_lsp_range(&lines, &edit.span), edit.new_text))
+        .collect()
+}
+
+fn to_lsp_range(lines: &[&str], span: &Span) -> lsp::Range {
+    lsp::Range::new(
+        to_lsp_position(lines, span.start),
+        to_lsp_position(lines, span.end),
+    )
+}
+
+/// LSP 的列按 UTF-16 码元计，而 `Position` 的列按字符计
+fn to_lsp_position(lines: &[&str], position: Position) -> lsp::Position {
+    let character: usize = lines.get(position.line).map_or(0, |line| {
+        line.chars()
+            .take(position.column)
+            .map(char::len_utf16)
+            .sum()
+    });
+    lsp::Position::new(position.line as u32, character as u32)
+}
+
+/// LSP 位置在文本中的字节偏移，超出范围时取最近的合法位置
+fn offset_at(text: &str, position: lsp::Position) -> usize {
+    let mut line_start = 0;
+    for _ in 0..position.line {
+        match text[line_start..].find('\n') {
+            Some(i) => line_start += i + 1,
+            None => return text.len(),
+        }
+    }
+    let line = &text[line_start..];
+    let line = &line[..line.find('\n').unwrap_or(line.len())];
+    let mut character = 0;
+    for (i, c) in line.char_indices() {
+        if character >= position.character as usize {
+            return line_start + i;
+        }
+        character += c.len_utf16();
+    }
+    line_start + line.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positions_count_utf16_code_units() {
+        let text = "😀中文\nab";
+        let lines: Vec<&str> = text.split('\n').collect();
+        let position = to_lsp_position(&lines, Position { line: 0, column: 2 });
+        assert_eq!(position, lsp::Position::new(0, 3));
+        assert_eq!(offset_at(text, lsp::Position::new(0, 3)), "😀中".len());
+        assert_eq!(offset_at(text, lsp::Position::new(1, 9)), text.len());
+        assert_eq!(offset_at(text, lsp::Position::new(5, 0)), text.len());
+    }
+
+    #[test]
+    fn lsp_edits_rebuild_output() {
+        let input = "😀中文abc\n\n中文123def\n";
+        let output = "😀中文 abc\n\n中文 123 def\n";
+        let mut text = input.to_string();
+        // 从后往前应用，前面的位置不受影响
+        for edit in to_lsp_edits(input, output).iter().rev() {
+            let range = offset_at(&text, edit.range.start)..offset_at(&text, edit.range.end);
+            text.replace_range(range, &edit.new_text);
+        }
+        assert_eq!(text, output);
+    }
+
+    fn server() -> (Server, Connection) {
+        let (connection, client) = Connection::memory();
+        let server = Server {
+            connection,
+            overrides: ConfigLayer::default(),
+            resolver: ConfigResolver::new(None, ConfigLayer::default()).unwrap(),
+            documents: HashMap::new(),
+        };
+        (server, client)
+    }
+
+    fn open(server: &mut Server, uri: &Url, text: &str) {
+        let params = lsp::DidOpenTextDocumentParams {
+            text_document: lsp::TextDocumentItem::new(
+                uri.clone(),
+                "markdown".to_string(),
+                1,
+                text.to_string(),
+            ),
+        };
+        let notification = Notification::new(DidOpenTextDocument::METHOD.to_string(), params);
+        server.handle_notification(notification).unwrap();
+    }
+
+    #[test]
+    fn formatting_returns_edits_and_publishes_diagnostics() {
+        let (mut server, client) = server();
+        let uri = Url::parse("file:///rustdown-test/a.md").unwrap();
+        open(&mut server, &uri, "中文abc\n\n```\n");
+        let Ok(Message::Notification(published)) = client.receiver.try_recv() else {
+            panic!("打开文档后应发布诊断信息");
+        };
+        let params: lsp::PublishDiagnosticsParams = parse_params(published.params).unwrap();
+        assert_eq!(params.diagnostics.len(), 1);
+
+        let params = lsp::DocumentFormattingParams {
+            text_document: lsp::TextDocumentIdentifier::new(uri),
+            options: lsp::FormattingOptions::default(),
+            work_done_progress_params: Default::default(),
+        };
+        let edits = server.formatting(params).unwrap();
+        // 第一处在中英文之间加空格，其后补上缺少的结束围栏
+        assert_eq!(edits.len(), 2);
+        assert_eq!(edits[0].range.start, lsp::Position::new(0, 2));
+        assert_eq!(edits[0].new_text, " ");
+    }
+
+    #[test]
+    fn invalid_notification_is_an_error_but_not_a_disconnect() {
+        let (mut server, _client) = server();
+        let notification = Notification::new(
+            DidOpenTextDocument::METHOD.to_string(),
+            serde_json::json!({"bogus": 1}),
+        );
+        let error = server.handle_notification(notification).unwrap_err();
+        assert!(!error.is::<Disconnected>());
+    }
+}
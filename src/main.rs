@@ -1,31 +1,350 @@
-mod config;
-mod formatter;
-mod parser;
-mod pipeline;
-use config::Config;
-use pipeline::format_string;
+mod files;
+mod lsp;
+mod resolver;
+
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use resolver::ConfigResolver;
+use rustdown_formatter::{
+    Config, ConfigLayer, Diagnostic, DiagnosticKind, Formatted, format, format_range, text_edits,
+};
+use similar::TextDiff;
 use std::env;
 use std::fs;
 use std::io::{self, BufReader, BufWriter, Read, Write};
 use std::path::Path;
 
+/// 出错（参数错误、文件不存在、IO 失败等）时的退出码
+const EXIT_ERROR: i32 = 1;
+/// `--check` 模式下存在未格式化文件时的退出码
+const EXIT_CHECK_FAILED: i32 = 2;
+
+const USAGE: &str = "\
+用法: rustdown-formatter [选项] <文件|目录|glob>...
+      rustdown-formatter [选项] -        (从 stdin 读取，结果写到 stdout)
+      rustdown-formatter lsp             (通过 stdio 提供 Language Server Protocol 服务)
+
+`lsp` 作为第一个参数时总是启动语言服务，格式化名为 lsp 的文件请写作 `./lsp` 或 `-- lsp`；
+`--` 之后的参数都视为输入，即使以 - 开头。
+
+目录会被递归遍历，其中的 .md / .markdown 文件都会被并行格式化；
+遍历时会跳过 .gitignore、.ignore、.rustdownignore、.git/info/exclude 与 git 全局
+忽略文件（core.excludesFile）中忽略的路径，以及以 . 开头的隐藏文件和目录；
+隐藏目录中的文件需要直接给出，如 `rustdown-formatter .github`。
+配置从文件所在目录逐级向上查找 rustdown.toml / .rustdownrc.json；
+优先级: 默认值 < 配置文件 < 环境变量 RUSTDOWN_CONFIG (JSON) < --set。
+
+选项:
+  --stdin     等同于 `-`
+  --check     只检查文件是否已格式化，不写回；存在未格式化文件时以退出码 2 退出
+  --exclude <模式>
+              排除匹配的路径，语法同 .gitignore，可重复指定
+  --diff      不写回，而是向 stdout 输出原文与格式化结果之间的 unified diff
+  --edits     不写回，而是为每个输入向 stdout 输出一行 JSON：
+              {\"file\": 路径, \"edits\": [{\"span\": 范围, \"new_text\": 替换文本}, ...]}，
+              范围均相对于原文且互不重叠，全部应用后即得到格式化结果
+  --strict    格式化过程中出现警告（如代码块格式化工具运行失败）时以退出码 1 退出
+  --config <文件>
+              使用指定的配置文件，不再自动查找
+  --set <键>=<值>
+              覆盖单个配置项，值按 JSON 解析，解析失败时视为字符串，可重复指定
+  --range <起始>:<结束>
+              只格式化与该范围相交的段落、标题、代码块等，其余部分保持不变；
+              默认为行号（从 1 开始，包含两端），数字后加 b 表示字节偏移
+              （从 0 开始，不含结束），如 `--range 120b:480b`；只能用于单个输入
+  -h, --help  显示本帮助";
+
+/// 命令行选项
+struct Options {
+    check: bool,
+    diff: bool,
+    edits: bool,
+    strict: bool,
+    excludes: Vec<String>,
+    config_path: Option<String>,
+    overrides: ConfigLayer,
+    range: Option<RangeSpec>,
+    inputs: Vec<String>,
+}
+
+/// `--range` 指定的范围
+#[derive(Clone, Copy)]
+enum RangeSpec {
+    /// 行号，从 1 开始，包含两端
+    Lines(usize, usize),
+    /// 字节偏移，从 0 开始，不含结束
+    Bytes(usize, usize),
+}
+
+/// 单个输入的处理结果
+struct Outcome {
+    /// 格式化是否会改变内容
+    changed: bool,
+    /// `--check` / `--diff` / `--edits` 模式下需要输出到 stdout 的报告
+    report: Option<String>,
+    /// 需要输出到 stderr 的警告（格式化过程中的诊断信息）
+    warnings: Vec<String>,
+}
+
 fn main() -> io::Result<()> {
-    // 获取命令行参数
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        eprintln!("用法: rustdown-formatter <文件路径>");
-        std::process::exit(1);
+    if env::args().nth(1).as_deref() == Some("lsp") {
+        lsp::run().unwrap_or_else(|e| exit_with_error(e));
+        return Ok(());
+    }
+
+    let options = parse_args(env::args().skip(1));
+    let mut resolver =
+        ConfigResolver::new(options.config_path.as_deref(), options.overrides.clone())
+            .unwrap_or_else(|e| exit_with_error(e));
+
+    if options.inputs[0] == "-" {
+        // stdin 没有所在目录，从当前目录开始查找配置
+        let config = resolver
+            .resolve_dir(Path::new("."))
+            .unwrap_or_else(|e| exit_with_error(e));
+        let outcome = format_stdin(&options, &config).unwrap_or_else(|e| exit_with_error(e.into()));
+        if let Some(report) = outcome.report {
+            io::stdout().lock().write_all(report.as_bytes())?;
+        }
+        for warning in &outcome.warnings {
+            eprintln!("{}", warning);
+        }
+        if options.strict && !outcome.warnings.is_empty() {
+            std::process::exit(EXIT_ERROR);
+        }
+        if options.check && outcome.changed {
+            std::process::exit(EXIT_CHECK_FAILED);
+        }
+        return Ok(());
     }
 
-    let file_path = &args[1];
-    let path = Path::new(file_path);
+    let files = files::build_excludes(&options.excludes)
+        .and_then(|excludes| files::collect_files(&options.inputs, &excludes))
+        .unwrap_or_else(|e| exit_with_error(e));
+    if options.range.is_some() && files.len() != 1 {
+        exit_with_error(anyhow::anyhow!(
+            "--range 只能用于单个文件，而输入展开后共有 {} 个文件",
+            files.len()
+        ));
+    }
+
+    // 配置文件的查找与解析在格式化之前串行完成，每个配置文件只解析一次
+    let configs = files
+        .iter()
+        .map(|path| resolver.resolve_file(path))
+        .collect::<anyhow::Result<Vec<_>>>()
+        .unwrap_or_else(|e| exit_with_error(e));
+
+    let results = files
+        .par_iter()
+        .zip(&configs)
+        .map(|(path, config)| format_file(path, &options, config))
+        .collect::<Vec<_>>();
 
-    // 检查文件是否存在
-    if !path.exists() {
-        eprintln!("错误: 文件 '{}' 不存在", file_path);
-        std::process::exit(1);
+    // 按文件顺序输出报告，保证并行格式化时结果仍然稳定
+    let stdout = io::stdout();
+    let mut writer = BufWriter::new(stdout.lock());
+    let mut changed = false;
+    let mut warned = false;
+    let mut failed = false;
+    for (path, result) in files.iter().zip(results) {
+        match result {
+            Ok(outcome) => {
+                changed |= outcome.changed;
+                warned |= !outcome.warnings.is_empty();
+                if let Some(report) = outcome.report {
+                    writer.write_all(report.as_bytes())?;
+                }
+                for warning in &outcome.warnings {
+                    eprintln!("{}", warning);
+                }
+            }
+            Err(e) => {
+                eprintln!("错误: 无法格式化 '{}': {}", path.display(), e);
+                failed = true;
+            }
+        }
     }
 
+    writer.flush()?;
+
+    if failed || (options.strict && warned) {
+        std::process::exit(EXIT_ERROR);
+    }
+    if options.check && changed {
+        std::process::exit(EXIT_CHECK_FAILED);
+    }
+    Ok(())
+}
+
+fn exit_with_error(error: anyhow::Error) -> ! {
+    eprintln!("错误: {:#}", error);
+    std::process::exit(EXIT_ERROR);
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("错误: {}", message);
+    eprintln!("{}", USAGE);
+    std::process::exit(EXIT_ERROR);
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Options {
+    let mut check = false;
+    let mut diff = false;
+    let mut edits = false;
+    let mut strict = false;
+    let mut excludes = Vec::new();
+    let mut config_path = None;
+    let mut overrides = ConfigLayer::default();
+    let mut range = None;
+    let mut inputs = Vec::new();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            inputs.extend(args.by_ref());
+            break;
+        }
+        // 带参数的选项同时支持 `--name value` 与 `--name=value`
+        let (name, inline_value) = match arg.split_once('=') {
+            Some((name, value)) if name.starts_with("--") => (name, Some(value.to_string())),
+            _ => (arg.as_str(), None),
+        };
+        let mut value = || {
+            inline_value
+                .clone()
+                .or_else(|| args.next())
+                .unwrap_or_else(|| usage_error(&format!("{} 需要一个参数", name)))
+        };
+        match name {
+            "--check" => check = true,
+            "--diff" => diff = true,
+            "--edits" => edits = true,
+            "--strict" => strict = true,
+            "--exclude" => excludes.push(value()),
+            "--config" => config_path = Some(value()),
+            "--set" => {
+                let assignment = value();
+                let Some((key, raw)) = assignment.split_once('=') else {
+                    usage_error(&format!(
+                        "--set 的参数应为 <键>=<值>，而不是 '{}'",
+                        assignment
+                    ));
+                };
+                let value = serde_json::from_str(raw)
+                    .unwrap_or_else(|_| serde_json::Value::String(raw.to_string()));
+                if let Err(e) = overrides.set(key, value) {
+                    usage_error(&format!("--set {} 无效: {:#}", assignment, e));
+                }
+            }
+            "--range" => {
+                let spec = value();
+                range = Some(parse_range(&spec).unwrap_or_else(|| {
+                    usage_error(&format!(
+                        "--range 的参数应为 <起始>:<结束>，而不是 '{}'",
+                        spec
+                    ))
+                }));
+            }
+            "-" | "--stdin" => inputs.push("-".to_string()),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            s if s.starts_with('-') => usage_error(&format!("未知选项 '{}'", s)),
+            _ => inputs.push(arg),
+        }
+    }
+    if diff && edits {
+        usage_error("--diff 与 --edits 不能同时使用");
+    }
+    if inputs.is_empty() {
+        usage_error("需要至少指定一个输入");
+    }
+    if inputs.len() > 1 && inputs.iter().any(|input| input == "-") {
+        usage_error("stdin 输入 `-` 不能与其它输入同时使用");
+    }
+    Options {
+        check,
+        diff,
+        edits,
+        strict,
+        excludes,
+        config_path,
+        overrides,
+        range,
+        inputs,
+    }
+}
+
+/// 解析 `--range` 的参数：`12:30` 为行号，`100b:250b` 为字节偏移
+fn parse_range(spec: &str) -> Option<RangeSpec> {
+    let (start, end) = spec.split_once(':')?;
+    match (start.strip_suffix('b'), end.strip_suffix('b')) {
+        (Some(start), Some(end)) => {
+            let (start, end) = (start.parse().ok()?, end.parse().ok()?);
+            (start <= end).then_some(RangeSpec::Bytes(start, end))
+        }
+        (None, None) => {
+            let (start, end) = (start.parse().ok()?, end.parse().ok()?);
+            (1 <= start && start <= end).then_some(RangeSpec::Lines(start, end))
+        }
+        _ => None,
+    }
+}
+
+/// 按 `--range` 格式化整篇文档或其中的一部分
+fn format_content(content: &str, config: &Config, options: &Options) -> io::Result<Formatted> {
+    let result = match options.range {
+        None => format(content, config),
+        Some(RangeSpec::Bytes(start, end)) => format_range(content, start..end, config),
+        Some(RangeSpec::Lines(first, last)) => {
+            // 第 first 行行首到第 last 行行尾（不含换行符）
+            let mut line_starts =
+                std::iter::once(0).chain(content.match_indices('\n').map(|(i, _)| i + 1));
+            let Some(start) = line_starts.nth(first - 1) else {
+                return Err(io::Error::other(format!(
+                    "--range 的起始行 {} 超出了文件的行数",
+                    first
+                )));
+            };
+            let end = line_starts
+                .nth(last - first)
+                .map_or(content.len(), |next_line| next_line - 1);
+            format_range(content, start..end, config)
+        }
+    };
+    result.map_err(io::Error::other)
+}
+
+/// 从 stdin 读取 markdown，格式化后写到 stdout，不触碰磁盘
+fn format_stdin(options: &Options, config: &Config) -> io::Result<Outcome> {
+    let mut content = String::new();
+    io::stdin().lock().read_to_string(&mut content)?;
+
+    let formatted = format_content(&content, config, options)?;
+    let changed = formatted.changed;
+    let warnings = describe_diagnostics("<stdin>", &formatted.diagnostics);
+    let formatted = formatted.output;
+
+    if options.check || options.diff || options.edits {
+        return Ok(Outcome {
+            changed,
+            report: report_change("<stdin>", &content, &formatted, options),
+            warnings,
+        });
+    }
+
+    let stdout = io::stdout();
+    let mut writer = BufWriter::new(stdout.lock());
+    writer.write_all(formatted.as_bytes())?;
+    writer.flush()?;
+    Ok(Outcome {
+        changed,
+        report: None,
+        warnings,
+    })
+}
+
+/// 格式化单个文件并写回；`--check` / `--diff` / `--edits` 模式下只报告而不写回
+fn format_file(path: &Path, options: &Options, config: &Config) -> io::Result<Outcome> {
     // 读取文件内容
     #[cfg(debug_assertions)]
     let t1 = std::time::Instant::now();
@@ -37,32 +356,130 @@ fn main() -> io::Result<()> {
     #[cfg(debug_assertions)]
     let t2 = std::time::Instant::now();
 
-    let config = if let Ok(config_str) = std::env::var("RUSTDOWN_CONFIG") {
-        serde_json::from_str(&config_str).unwrap_or_else(|_| Config::default())
-    } else {
-        Config::default()
-    };
+    let name = path.display().to_string();
+    let formatted = format_content(&content, config, options)?;
+    let changed = formatted.changed;
+    let warnings = describe_diagnostics(&name, &formatted.diagnostics);
+    let formatted = formatted.output;
+
     #[cfg(debug_assertions)]
     let t3 = std::time::Instant::now();
-    let formatted = format_string(&content, &config);
 
-    #[cfg(debug_assertions)]
-    let t4 = std::time::Instant::now();
+    if options.check || options.diff || options.edits {
+        return Ok(Outcome {
+            changed,
+            report: report_change(&name, &content, &formatted, options),
+            warnings,
+        });
+    }
 
-    // 使用缓冲写入
-    let file = fs::File::create(path)?;
-    let mut writer = BufWriter::new(file);
-    writer.write_all(formatted.as_bytes())?;
-    writer.flush()?; // 确保所有数据都写入文件
+    // 内容没有变化时不必写回，避免无谓地更新修改时间
+    if changed {
+        // 使用缓冲写入
+        let file = fs::File::create(path)?;
+        let mut writer = BufWriter::new(file);
+        writer.write_all(formatted.as_bytes())?;
+        writer.flush()?; // 确保所有数据都写入文件
+    }
 
     #[cfg(debug_assertions)]
-    let t5 = std::time::Instant::now();
+    let t4 = std::time::Instant::now();
     #[cfg(debug_assertions)]
     {
-        println!("读取文件耗时: {:?}", t2 - t1);
-        println!("解析配置耗时: {:?}", t3 - t2);
-        println!("格式化耗时: {:?}", t4 - t3);
-        println!("写回文件耗时: {:?}", t5 - t4);
+        // 调试信息写到 stderr，避免污染 stdout
+        eprintln!("读取文件耗时: {:?}", t2 - t1);
+        eprintln!("格式化耗时: {:?}", t3 - t2);
+        eprintln!("写回文件耗时: {:?}", t4 - t3);
+    }
+    Ok(Outcome {
+        changed,
+        report: None,
+        warnings,
+    })
+}
+
+/// 生成 `--check` / `--diff` / `--edits` 模式下的报告：`--edits` 时为一行 JSON，
+/// `--diff` 时为 unified diff，否则为需要格式化的路径；后两者在内容不变时没有报告
+fn report_change(name: &str, original: &str, formatted: &str, options: &Options) -> Option<String> {
+    if options.edits {
+        let report = serde_json::json!({
+            "file": name,
+            "edits": text_edits(original, formatted),
+        });
+        return Some(format!("{}\n", report));
+    }
+    if original == formatted {
+        return None;
+    }
+    if options.diff {
+        let diff = TextDiff::from_lines(original, formatted);
+        Some(
+            diff.unified_diff()
+                .context_radius(3)
+                .header(name, name)
+                .to_string(),
+        )
+    } else {
+        Some(format!("{}\n", name))
+    }
+}
+
+/// 将诊断信息整理为 `警告: 文件:行: 说明` 形式的文本，附带外部工具的 stderr
+fn describe_diagnostics(name: &str, diagnostics: &[Diagnostic]) -> Vec<String> {
+    diagnostics
+        .iter()
+        .map(|diagnostic| {
+            let line = diagnostic.span.start.line + 1;
+            let mut warning = format!("警告: {}:{}: {}", name, line, diagnostic.message);
+            match &diagnostic.kind {
+                DiagnosticKind::ExternalFormatter { stderr, .. }
+                | DiagnosticKind::FrontMatter { stderr }
+                    if !stderr.trim().is_empty() =>
+                {
+                    for stderr_line in stderr.trim_end().lines() {
+                        warning.push_str("\n    ");
+                        warning.push_str(stderr_line);
+                    }
+                }
+                _ => {}
+            }
+            warning
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(args: &[&str]) -> Options {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn arguments_after_double_dash_are_inputs() {
+        let options = options(&["--check", "--", "lsp", "--diff"]);
+        assert!(options.check && !options.diff);
+        assert_eq!(options.inputs, ["lsp", "--diff"]);
+    }
+
+    #[test]
+    fn parse_range_accepts_lines_and_bytes() {
+        assert!(matches!(parse_range("2:3"), Some(RangeSpec::Lines(2, 3))));
+        assert!(matches!(parse_range("5b:9b"), Some(RangeSpec::Bytes(5, 9))));
+        assert!(parse_range("0:1").is_none());
+        assert!(parse_range("3:2").is_none());
+        assert!(parse_range("1b:2").is_none());
+    }
+
+    #[test]
+    fn range_selects_lines_or_bytes() {
+        let content = "中文English\n\n第二段123\n";
+        let config = Config::default();
+        let lines = format_content(content, &config, &options(&["--range", "3:3", "-"])).unwrap();
+        assert_eq!(lines.output, "中文English\n\n第二段 123\n");
+        let bytes = format_content(content, &config, &options(&["--range=0b:1b", "-"])).unwrap();
+        assert_eq!(bytes.output, "中文 English\n\n第二段123\n");
+        assert!(format_content(content, &config, &options(&["--range", "9:9", "-"])).is_err());
     }
-    Ok(())
 }
@@ -1,27 +1,258 @@
+use crate::diagnostic::{Diagnostic, DiagnosticKind};
+use crate::span::{LineIndex, Position, Span};
+use self_cell::self_cell;
+use std::ops::Range;
 use std::str::Chars;
 
+/// 引用块最多嵌套的层数，更深的引用块原样输出，避免递归过深
+const MAX_QUOTE_DEPTH: usize = 32;
+
 #[derive(Debug, PartialEq)]
-pub enum Token<'a> {
+pub enum TokenKind<'a> {
     Text(&'a str),
     Chinese(&'a str),
     English(&'a str),
     Number(&'a str),
     InlineMath(&'a str),
-    InlineCode(&'a str),
+    InlineCode(&'a str, usize), // (content, 原来定界符中 ` 的个数)
     BlockMath(&'a str),
-    CodeBlock { language: &'a str, content: &'a str },
     NewLine,
     Title(Vec<Token<'a>>, usize), // (text, level)
     FakeCodeBlock,                // 用于占位，表示这是一个代码块，实际内容在 code_block_tokens 中
+    ListItem(ListItem),           // 列表项标记，包含其前面的缩进与后面的空白，内容紧随其后
+    ListIndent(usize, usize),     // 列表项续行的缩进：所属列表项的嵌套层数（从 1 开始），超出其内容起始列的列数
+    Table(Table<'a>),             // GFM 表格，包含表头、分隔行与所有数据行
+    Link(Link<'a>),               // 链接或图片，只有其中的文本参与格式化
+    Autolink(&'a str),            // 尖括号中的链接，如 `<https://example.com>`，包含尖括号
+    Url(&'a str),                 // 正文中的裸链接，如 `https://example.com/路径`
+    LinkDefinition(&'a str),      // 链接引用定义所在的整行，如 `[label]: https://example.com`
+    Emphasis(Emphasis<'a>),       // 强调、加粗或删除线，其中的文本参与格式化
+    BlockQuote(BlockQuote<'a>),   // 引用块，内容作为嵌套文档格式化
+    FrontMatter(FrontMatter<'a>), // 文档开头的 front matter，包含两端的分隔线
+    Ignored(&'a str),             // 忽略指令所包围的原文，包含指令所在的行，原样输出
+    HtmlBlock(&'a str),           // HTML 块，如 `<div>` 到空行之间的各行
+    InlineHtml(&'a str),          // 行内的 HTML 标签、注释等，如 `<kbd>`、`</kbd>`、`<br/>`
+    IndentedCode(&'a str),        // 缩进至少 4 列的代码块，原样输出
+}
+
+#[derive(Debug, PartialEq)]
+pub struct FrontMatter<'a> {
+    pub kind: FrontMatterKind,
+    /// 两条分隔线之间的内容，不含分隔线
+    pub content: &'a str,
+    /// 包含两端分隔线的原文
+    pub source: &'a str,
+}
+
+/// front matter 的格式，由开头的分隔线决定
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrontMatterKind {
+    /// `---` 开头，以 `---` 或 `...` 结束
+    Yaml,
+    /// `+++` 开头，以 `+++` 结束
+    Toml,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct BlockQuote<'a> {
+    /// 引用块的原文，包含各行的 `>`
+    pub source: &'a str,
+    /// 去掉各行 `>` 前缀后的内容及其解析结果，其中可以再嵌套引用块
+    document: QuoteDocument,
+    /// 嵌套超过 `MAX_QUOTE_DEPTH` 层时不解析内容，整个引用块原样输出
+    too_deep: bool,
+    /// 引用块第一行的行号
+    first_line: usize,
+    /// 内容中各行与原文的对应关系
+    lines: Vec<QuoteLine>,
+}
+
+#[derive(Debug, PartialEq)]
+struct QuoteLine {
+    /// 该行在内容中的起始字节偏移
+    content_start: usize,
+    /// 该行去掉前缀后在输入中的起始字节偏移
+    source_start: usize,
+    /// 前缀所占的列数
+    prefix_width: usize,
+}
+
+/// 引用块内容解析出的 token，借用自 `QuoteDocument` 中的内容
+#[derive(Debug, PartialEq)]
+pub(crate) struct QuoteTokens<'a> {
+    pub(crate) tokens: Vec<Token<'a>>,
+    pub(crate) code_blocks: Vec<CodeBlock<'a>>,
+}
+
+self_cell!(
+    struct QuoteDocument {
+        owner: String,
+
+        #[covariant]
+        dependent: QuoteTokens,
+    }
+
+    impl {Debug, PartialEq}
+);
+
+impl BlockQuote<'_> {
+    /// 去掉各行 `>` 前缀后的内容
+    pub fn content(&self) -> &str {
+        self.document.borrow_owner()
+    }
+
+    /// 内容解析出的 token；嵌套过深而未解析时为 `None`
+    pub(crate) fn tokens(&self) -> Option<&QuoteTokens<'_>> {
+        (!self.too_deep).then(|| self.document.borrow_dependent())
+    }
+
+    /// 将内容中的范围换算为在输入中的范围
+    pub(crate) fn source_span(&self, span: &Span) -> Span {
+        let (start_byte, start) = self.source_position(span.bytes.start, span.start);
+        let (end_byte, end) = self.source_position(span.bytes.end, span.end);
+        Span {
+            bytes: start_byte..end_byte,
+            start,
+            end,
+        }
+    }
+
+    fn source_position(&self, byte: usize, position: Position) -> (usize, Position) {
+        let line = &self.lines[position.line.min(self.lines.len() - 1)];
+        (
+            line.source_start + byte - line.content_start,
+            Position {
+                line: self.first_line + position.line,
+                column: line.prefix_width + position.column,
+            },
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmphasisKind {
+    /// `*强调*` 或 `_强调_`
+    Emphasis,
+    /// `**加粗**` 或 `__加粗__`
+    Strong,
+    /// `~~删除线~~`
+    Strikethrough,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Emphasis<'a> {
+    pub kind: EmphasisKind,
+    /// 原文使用的定界符：`*`、`_` 或 `~`
+    pub delimiter: char,
+    /// 开始定界符前紧挨着字母或数字（如 `foo*bar*`），此时只能使用 `*`
+    pub word_before: bool,
+    /// 结束定界符后紧挨着字母或数字
+    pub word_after: bool,
+    /// 定界符之间的内容
+    pub content: Vec<Token<'a>>,
+    /// 包含定界符的原文
+    pub source: &'a str,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Link<'a> {
+    /// 是否为图片 `![...](...)`
+    pub image: bool,
+    /// 方括号中的文本（图片为替代文本）
+    pub text: Vec<Token<'a>>,
+    /// 方括号之后的部分，即 `(目标 "标题")` 或 `[label]`，原样保留
+    pub target: &'a str,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Table<'a> {
+    /// 各列的对齐方式，列数以表头为准
+    pub alignments: Vec<Alignment>,
+    /// 表头与各数据行（不含分隔行），每个单元格是解析后的 token
+    pub rows: Vec<Vec<Vec<Token<'a>>>>,
+    /// 表格的原文
+    pub source: &'a str,
+}
+
+/// 表格列的对齐方式，对应分隔行中的 `---`、`:--`、`:-:`、`--:`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Alignment {
+    None,
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListMarker {
+    /// `-`、`*` 或 `+`
+    Bullet(char),
+    /// `1.` 或 `1)`
+    Ordered { number: u64, delimiter: char },
 }
 
+impl ListMarker {
+    /// 标记不同（无序列表换了符号，或有序列表换了分隔符）时开始一个新的列表
+    fn same_list(&self, other: &ListMarker) -> bool {
+        match (self, other) {
+            (ListMarker::Bullet(a), ListMarker::Bullet(b)) => a == b,
+            (ListMarker::Ordered { delimiter: a, .. }, ListMarker::Ordered { delimiter: b, .. }) => {
+                a == b
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListItem {
+    pub marker: ListMarker,
+    /// 嵌套层级，顶层列表为 0
+    pub level: usize,
+    /// 是否是该层级上一个新列表的第一项
+    pub starts_list: bool,
+    /// 新列表紧接在同一层级标记不同的另一个列表之后，两者的标记需要保持不同
+    pub follows_list: bool,
+    /// 标记后没有内容
+    pub empty: bool,
+}
+
+/// 尚未结束的列表项
+struct OpenListItem {
+    marker: ListMarker,
+    /// 列表项内容的起始列，续行缩进达到该列时属于这一项
+    content_indent: usize,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Token<'a> {
+    pub kind: TokenKind<'a>,
+    /// 在输入中的范围，包含 `$`、`` ` ``、`#` 等定界符
+    pub span: Span,
+}
+
+#[derive(Debug, PartialEq)]
 pub struct CodeBlock<'a>{
+    /// 规范化后的语言，用于选择格式化工具
     pub language: &'a str,
+    /// 信息字符串中原本的语言写法，如 `javascript`、`Python`
+    pub tag: &'a str,
+    /// 信息字符串中语言之后的部分，如 `,ignore`、` title="main.py"`，原样保留
+    pub attributes: &'a str,
+    /// 开始围栏，如 ```` ``` ````、`~~~~`；输出时结束围栏与之相同
+    pub fence: &'a str,
     pub content: &'a str,
+    /// 列表项中的代码块围栏所在的列，内容各行去掉这部分缩进后再格式化，输出时重新缩进
+    pub indent: usize,
+    /// 整个代码块（含围栏）在输入中的范围
+    pub span: Span,
+    /// 代码块内容在输入中的范围
+    pub content_span: Span,
 }
 
 pub struct Parser<'a> {
     input: &'a str,
+    lines: LineIndex<'a>,
     chars: Chars<'a>,
     current: Option<char>,
     byte_pos: usize, // 字节位置
@@ -30,6 +261,13 @@ pub struct Parser<'a> {
     text_start_byte: usize, // 当前文本块的起始字节位置,
     tokens: Vec<Token<'a>>,
     code_blocks: Vec<CodeBlock<'a>>, // 用于存储代码块 tokens，用于之后并行处理
+    diagnostics: Vec<Diagnostic>,    // 结构问题，如未闭合的代码块
+    at_line_start: bool,             // 下一个字符是否位于行首
+    after_blank_line: bool,          // 上一行是否为空行
+    list_stack: Vec<OpenListItem>,   // 从外到内尚未结束的列表项
+    allow_front_matter: bool,        // 开头是否可以是 front matter，引用块等嵌套内容中不可以
+    inline: bool,                    // 只解析行内内容（标题文本、表格单元格等），不识别代码块
+    quote_depth: usize,              // 所在引用块的嵌套层数，顶层文档为 0
 }
 
 impl<'a> Parser<'a> {
@@ -38,6 +276,7 @@ impl<'a> Parser<'a> {
         let current = chars.next();
         Parser {
             input,
+            lines: LineIndex::new(input),
             chars,
             current,
             byte_pos: 0,
@@ -46,6 +285,31 @@ impl<'a> Parser<'a> {
             text_start_byte: 0,
             tokens: Vec::with_capacity(input.len() / 4),
             code_blocks: Vec::new(),
+            diagnostics: Vec::new(),
+            at_line_start: true,
+            after_blank_line: false,
+            list_stack: Vec::new(),
+            allow_front_matter: true,
+            inline: false,
+            quote_depth: 0,
+        }
+    }
+
+    /// 解析单行的行内内容，不识别列表、表格、代码块等块级结构
+    pub(crate) fn inline(input: &'a str) -> Self {
+        Parser {
+            at_line_start: false,
+            allow_front_matter: false,
+            inline: true,
+            ..Parser::new(input)
+        }
+    }
+
+    /// 解析引用块等嵌套内容，开头的 `---` 不会被当作 front matter
+    pub(crate) fn nested(input: &'a str) -> Self {
+        Parser {
+            allow_front_matter: false,
+            ..Parser::new(input)
         }
     }
 
@@ -57,6 +321,10 @@ impl<'a> Parser<'a> {
         &self.code_blocks
     }
 
+    pub fn get_diagnostics(&self) -> &Vec<Diagnostic> {
+        &self.diagnostics
+    }
+
     fn jump_next_char(&mut self) {
         let current = self.current;
         if let Some(c) = current {
@@ -81,6 +349,12 @@ impl<'a> Parser<'a> {
         self.current
     }
 
+    /// 以 `start` 到当前位置为范围记录一个 token
+    fn push_token(&mut self, kind: TokenKind<'a>, start: usize) {
+        let span = self.lines.span(start..self.byte_pos);
+        self.tokens.push(Token { kind, span });
+    }
+
     #[inline]
     fn take_slice(&self, start_byte: usize, end_byte: usize) -> &'a str {
         &self.input[start_byte..end_byte]
@@ -88,12 +362,12 @@ impl<'a> Parser<'a> {
 
     fn judge_quote_start(&mut self) {
         for token in self.tokens.iter().rev() {
-            match token {
-                Token::NewLine => {
+            match &token.kind {
+                TokenKind::NewLine => {
                     self.inside_quote_block = true;
                     break;
                 }
-                Token::Text(s) if s.chars().all(char::is_whitespace) => continue,
+                TokenKind::Text(s) if s.chars().all(char::is_whitespace) => continue,
                 _ => {
                     break;
                 }
@@ -103,6 +377,7 @@ impl<'a> Parser<'a> {
 
     fn parse_title(&mut self) {
         self.flush_text();
+        let title_start = self.byte_pos;
         let mut level = 0;
         // 扫描全部的 #
         while let Some(c) = self.peek() {
@@ -113,20 +388,20 @@ impl<'a> Parser<'a> {
                 break;
             }
         }
-        if let Some(c) = self.current {
-            if !c.is_whitespace() {
-                // 不是标题
-                self.text_start_byte = self.byte_pos - level; // 回退到 # 位置
-                return;
-            }
+        if let Some(c) = self.current
+            && !c.is_whitespace()
+        {
+            // 不是标题
+            self.text_start_byte = self.byte_pos - level; // 回退到 # 位置
+            return;
         }
         for token in self.tokens.iter().rev() {
-            match token {
-                Token::NewLine => {
+            match &token.kind {
+                TokenKind::NewLine => {
                     // 是标题
                     break;
                 }
-                Token::Text(s) if s.chars().all(char::is_whitespace) => continue,
+                TokenKind::Text(s) if s.chars().all(char::is_whitespace) => continue,
                 _ => {
                     // 不是标题
                     self.text_start_byte = self.byte_pos - level; // 回退到 # 位置
@@ -149,23 +424,50 @@ impl<'a> Parser<'a> {
             }
             self.jump_next_char();
         }
-        let title_text = self.take_slice(start, self.byte_pos).trim();
-        let mut title_parser = Parser::new(title_text);
+        let line = self.take_slice(start, self.byte_pos);
+        let title_text = line.trim();
+        // 标题文本不在行首，其中的 `1.` 等不是列表标记
+        let mut title_parser = Parser::inline(title_text);
         title_parser.parse();
-        let title_tokens = title_parser.tokens;
-        self.tokens.push(Token::Title(title_tokens, level));
+        // 标题文本中的位置换算为在整个输入中的位置
+        let text_start = start + (line.len() - line.trim_start().len());
+        let origin = self.lines.position(text_start);
+        let title_tokens = title_parser
+            .tokens
+            .into_iter()
+            .map(|token| token.relative_to(text_start, origin))
+            .collect();
+        self.push_token(TokenKind::Title(title_tokens, level), title_start);
         self.text_start_byte = self.byte_pos;
     }
 
     pub fn parse(&mut self) {
+        if self.allow_front_matter && self.byte_pos == 0 {
+            self.parse_front_matter();
+        }
         while let Some(c) = self.peek() {
+            if self.at_line_start {
+                self.at_line_start = false;
+                self.parse_line_start();
+                continue;
+            }
             match c {
                 '>' => {
                     self.judge_quote_start();
                     self.jump_next_char();
                 }
                 '$' => self.parse_math(),
-                '`' => self.parse_code(),
+                '[' => self.parse_link(false),
+                '!' if self.peek_next() == Some('[') => self.parse_link(true),
+                '<' => self.parse_autolink(),
+                '`' | '~'
+                    if self.at_block_start()
+                        && let Some(fence_len) = fence_len(&self.input[self.byte_pos..]) =>
+                {
+                    self.parse_code_block(fence_len)
+                }
+                '*' | '_' | '~' => self.parse_emphasis(c),
+                '`' => self.parse_inline_code(),
                 '\r' => {
                     self.flush_text();
                     self.jump_next_char();
@@ -173,10 +475,12 @@ impl<'a> Parser<'a> {
                 }
                 '\n' => {
                     self.flush_text();
+                    let start = self.byte_pos;
                     self.jump_next_char();
-                    self.tokens.push(Token::NewLine);
+                    self.push_token(TokenKind::NewLine, start);
                     self.text_start_byte = self.byte_pos;
                     self.inside_quote_block = false;
+                    self.at_line_start = true;
                 }
                 '#' => {
                     self.parse_title();
@@ -190,58 +494,421 @@ impl<'a> Parser<'a> {
 
         // 处理剩余的文本
         self.flush_text();
+    }
+
+    /// 识别文档开头由 `---` 或 `+++` 包围的 front matter，没有结束的分隔线时按正文处理
+    fn parse_front_matter(&mut self) {
+        let mut lines = self.input.split('\n');
+        let (kind, closing): (_, &[&str]) = match lines.next().map(str::trim_end) {
+            Some("---") => (FrontMatterKind::Yaml, &["---", "..."]),
+            Some("+++") => (FrontMatterKind::Toml, &["+++"]),
+            _ => return,
+        };
+        let content_start = self.input.find('\n').map_or(self.input.len(), |i| i + 1);
+        let mut line_start = content_start;
+        for line in lines {
+            if closing.contains(&line.trim_end()) {
+                let end = line_start + line.len();
+                self.jump_bytes(end);
+                self.push_token(
+                    TokenKind::FrontMatter(FrontMatter {
+                        kind,
+                        content: self.take_slice(content_start, line_start),
+                        source: self.take_slice(0, end),
+                    }),
+                    0,
+                );
+                self.text_start_byte = self.byte_pos;
+                self.at_line_start = false;
+                return;
+            }
+            line_start += line.len() + 1;
+        }
+    }
+
+    /// 在行首识别列表项标记，以及列表项中续行的缩进
+    fn parse_line_start(&mut self) {
+        let rest = &self.input[self.byte_pos..];
+        let line = &rest[..rest.find('\n').unwrap_or(rest.len())];
+        let content = line.trim_start_matches([' ', '\t']);
+        let indent = &line[..line.len() - content.len()];
+        let width = indent_width(indent);
+        let is_blank = content.trim().is_empty();
+        let after_blank = std::mem::replace(&mut self.after_blank_line, is_blank);
+        if is_blank {
+            return;
+        }
 
-        // 如果是debug模式，将tokens解析情况写入一个测试文件中
-        #[cfg(debug_assertions)]
+        if let Some(directive) = ignore_directive(content) {
+            self.parse_ignored(directive);
+            return;
+        }
+        // 缩进代码块不能打断段落，因此只出现在空行之后
+        if self.list_stack.is_empty() && width >= 4 && (after_blank || self.byte_pos == 0) {
+            self.parse_indented_code();
+            return;
+        }
+        // 除 `<div>` 等已知的块级标签外，单独一行的标签不能打断段落
+        if self.list_stack.is_empty()
+            && width <= 3
+            && let Some(end) = html_block_start(content, after_blank || self.byte_pos == 0)
         {
-            use std::fs::File;
-            use std::io::Write;
-            let mut file = File::create("debug_tokens.txt").unwrap();
-            for token in &self.tokens {
-                writeln!(file, "{:?}", token).unwrap();
+            self.parse_html_block(end);
+            return;
+        }
+        if self.list_stack.is_empty() && width <= 3 && self.parse_table() {
+            return;
+        }
+        // 列表项中的引用块仍按普通文本处理
+        if self.list_stack.is_empty() && width <= 3 && content.starts_with('>') {
+            self.parse_block_quote();
+            return;
+        }
+
+        // 缩进超过 3 列（相对于所在列表项的内容）的是缩进代码块而不是列表项
+        let max_marker_indent = self.list_stack.last().map_or(0, |item| item.content_indent) + 3;
+        if width <= max_marker_indent
+            && !is_thematic_break(content)
+            && let Some((marker, marker_len)) = parse_list_marker(content)
+            && (after_blank || !self.list_stack.is_empty() || !self.after_paragraph_line()
+                || can_interrupt_paragraph(marker, &content[marker_len..]))
+        {
+            self.parse_list_item(marker, indent.len(), width, &content[marker_len..], marker_len);
+            return;
+        }
+
+        if self.list_stack.is_empty() {
+            return;
+        }
+        if after_blank {
+            // 空行之后缩进不足的行结束相应的列表项
+            let level = self
+                .list_stack
+                .iter()
+                .take_while(|item| item.content_indent <= width)
+                .count();
+            self.list_stack.truncate(level);
+            if level > 0 {
+                self.push_list_indent(level, indent.len(), width);
             }
+        } else if !indent.is_empty() {
+            // 紧接着上一行的续行属于最内层的列表项
+            self.push_list_indent(self.list_stack.len(), indent.len(), width);
         }
     }
 
-    fn flush_text(&mut self) {
-        if self.byte_pos > self.text_start_byte {
-            self.tokens.push(Token::Text(
-                self.take_slice(self.text_start_byte, self.byte_pos),
-            ));
+    /// 上一行是否是段落中的文字，而不是标题、代码块等块级结构
+    fn after_paragraph_line(&self) -> bool {
+        let mut kinds = self.tokens.iter().rev().map(|token| &token.kind);
+        matches!(kinds.next(), Some(TokenKind::NewLine))
+            && matches!(
+                kinds.next(),
+                Some(
+                    TokenKind::Text(_)
+                        | TokenKind::Chinese(_)
+                        | TokenKind::English(_)
+                        | TokenKind::Number(_)
+                        | TokenKind::InlineMath(_)
+                        | TokenKind::InlineCode(..)
+                        | TokenKind::Link(_)
+                        | TokenKind::Autolink(_)
+                        | TokenKind::Url(_)
+                        | TokenKind::Emphasis(_)
+                        | TokenKind::InlineHtml(_)
+                )
+            )
+    }
+
+    fn parse_list_item(
+        &mut self,
+        marker: ListMarker,
+        indent_len: usize,
+        width: usize,
+        after_marker: &str,
+        marker_len: usize,
+    ) {
+        // 缩进不足以成为子项时，结束更深的列表项；最后结束的一项与新的一项同级
+        let mut sibling = None;
+        while let Some(item) = self.list_stack.last()
+            && width < item.content_indent
+        {
+            sibling = self.list_stack.pop();
         }
+        let starts_list = !sibling.as_ref().is_some_and(|item| item.marker.same_list(&marker));
+        let follows_list = starts_list && sibling.is_some();
+
+        let rest = after_marker.trim_start_matches([' ', '\t']);
+        let empty = rest.trim().is_empty();
+        let spaces = after_marker.len() - rest.len();
+        // 标记后超过 4 个空格时内容是缩进代码块，只把第一个空格算作标记的一部分；
+        // 空列表项后面的空白则全部算作标记的一部分
+        let (spaces, content_spaces) = match spaces {
+            _ if empty => (spaces, 1),
+            5.. => (1, 1),
+            _ => (spaces, spaces),
+        };
+        let content_indent = width + marker_len + content_spaces;
+
+        let level = self.list_stack.len();
+        self.list_stack.push(OpenListItem {
+            marker,
+            content_indent,
+        });
+        let start = self.byte_pos;
+        self.jump_bytes(indent_len + marker_len + spaces);
+        self.push_token(
+            TokenKind::ListItem(ListItem {
+                marker,
+                level,
+                starts_list,
+                follows_list,
+                empty,
+            }),
+            start,
+        );
+        self.text_start_byte = self.byte_pos;
     }
 
-    fn parse_math(&mut self) {
+    /// 从当前行开始识别 GFM 表格：含 `|` 的表头行，紧跟列数相同的分隔行，
+    /// 之后直到空行或不含 `|` 的行为止都是数据行
+    fn parse_table(&mut self) -> bool {
+        let start = self.byte_pos;
+        let mut lines = self.input[start..].split('\n');
+        let (Some(header), Some(delimiter)) = (lines.next(), lines.next()) else {
+            return false;
+        };
+        if !header.contains('|') || delimiter.trim().is_empty() {
+            return false;
+        }
+        let header_cells = split_table_row(header);
+        let Some(alignments) = split_table_row(delimiter)
+            .into_iter()
+            .map(|cell| parse_alignment(&delimiter[cell]))
+            .collect::<Option<Vec<_>>>()
+        else {
+            return false;
+        };
+        if alignments.len() != header_cells.len() {
+            return false;
+        }
+
+        let mut rows = vec![self.parse_table_row(start, header)];
+        let mut end = start + header.len() + 1 + delimiter.len();
+        for line in lines {
+            if line.trim().is_empty() || !line.contains('|') {
+                break;
+            }
+            rows.push(self.parse_table_row(end + 1, line));
+            end += 1 + line.len();
+        }
+        self.jump_bytes(end - start);
+        self.push_token(
+            TokenKind::Table(Table {
+                alignments,
+                rows,
+                source: self.take_slice(start, end),
+            }),
+            start,
+        );
+        self.text_start_byte = self.byte_pos;
+        true
+    }
+
+    /// 从忽略指令所在的行开始，取出不参与格式化的原文：`ignore-next-line` 之后的一行，
+    /// 或 `ignore-start` 到 `ignore-end`（缺少时到文档末尾）之间的所有行
+    fn parse_ignored(&mut self, directive: IgnoreDirective) {
+        let start = self.byte_pos;
+        let rest = &self.input[start..];
+        let mut lines = rest.split('\n');
+        let mut end = lines.next().map_or(0, str::len);
+        match directive {
+            IgnoreDirective::NextLine => {
+                if let Some(line) = lines.next() {
+                    end += 1 + line.len();
+                    // 下一行是代码块或公式块的开头时忽略整个块，以免只忽略了开头的围栏
+                    if let Some(fence) = block_fence(line) {
+                        for line in lines {
+                            end += 1 + line.len();
+                            if is_closing_fence(line.trim(), fence) {
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+            IgnoreDirective::Start => {
+                for line in lines {
+                    end += 1 + line.len();
+                    if ignore_directive(line) == Some(IgnoreDirective::End) {
+                        break;
+                    }
+                }
+            }
+            // 单独出现的结束指令本身原样保留
+            IgnoreDirective::End => {}
+        }
         self.flush_text();
-        self.jump_next_char();
-        if self.current == Some('$') {
-            self.jump_next_char();
-            if !self.inside_quote_block {
-                self.parse_block_math();
-            } else {
-                self.text_start_byte = self.byte_pos - 2; // 跳过 $$
-                return;
+        self.jump_bytes(end);
+        self.push_token(TokenKind::Ignored(self.take_slice(start, start + end)), start);
+        self.text_start_byte = self.byte_pos;
+    }
+
+    /// 从当前行开始解析 HTML 块，直到满足 `end` 所描述的结束条件
+    fn parse_html_block(&mut self, end: HtmlBlockEnd) {
+        let start = self.byte_pos;
+        let mut block_end = start;
+        let mut line_start = start;
+        for (i, line) in self.input[start..].split('\n').enumerate() {
+            match end {
+                HtmlBlockEnd::BlankLine if i > 0 && line.trim().is_empty() => break,
+                HtmlBlockEnd::BlankLine => block_end = line_start + line.len(),
+                HtmlBlockEnd::Contains(markers) => {
+                    block_end = line_start + line.len();
+                    let line = line.to_ascii_lowercase();
+                    if markers.iter().any(|marker| line.contains(marker)) {
+                        break;
+                    }
+                }
             }
-        } else {
-            self.parse_inline_math();
+            line_start += line.len() + 1;
+        }
+        self.flush_text();
+        self.jump_bytes(block_end - start);
+        self.push_token(TokenKind::HtmlBlock(self.take_slice(start, block_end)), start);
+        self.text_start_byte = self.byte_pos;
+    }
+
+    /// 从当前行开始解析引用块，直到第一个不以 `>` 开头的行为止。
+    /// 各行去掉 `>` 及其后的一个空格后作为嵌套文档解析，token 与内容一起保存在引用块中，
+    /// 其中的问题（如未闭合的代码块）换算为在输入中的位置后记录
+    fn parse_block_quote(&mut self) {
+        let start = self.byte_pos;
+        let first_line = self.lines.position(start).line;
+        let mut content = String::new();
+        let mut lines = Vec::new();
+        let mut line_start = start;
+        for line in self.input[start..].split('\n') {
+            let trimmed = line.trim_start_matches(' ');
+            let Some(rest) = trimmed
+                .strip_prefix('>')
+                .filter(|_| line.len() - trimmed.len() <= 3)
+            else {
+                break;
+            };
+            let rest = rest.strip_prefix(' ').unwrap_or(rest);
+            let prefix_len = line.len() - rest.len();
+            if !lines.is_empty() {
+                content.push('\n');
+            }
+            lines.push(QuoteLine {
+                content_start: content.len(),
+                source_start: line_start + prefix_len,
+                prefix_width: prefix_len,
+            });
+            content.push_str(rest);
+            line_start += line.len() + 1;
         }
+        // 不包含最后一行的换行符
+        let end = line_start - 1;
+
+        let depth = self.quote_depth + 1;
+        let too_deep = depth > MAX_QUOTE_DEPTH;
+        let mut diagnostics = Vec::new();
+        let document = QuoteDocument::new(content, |content| {
+            if too_deep {
+                return QuoteTokens {
+                    tokens: Vec::new(),
+                    code_blocks: Vec::new(),
+                };
+            }
+            let mut content_parser = Parser {
+                quote_depth: depth,
+                ..Parser::nested(content)
+            };
+            content_parser.parse();
+            diagnostics = content_parser.diagnostics;
+            QuoteTokens {
+                tokens: content_parser.tokens,
+                code_blocks: content_parser.code_blocks,
+            }
+        });
+        let quote = BlockQuote {
+            source: self.take_slice(start, end),
+            document,
+            too_deep,
+            first_line,
+            lines,
+        };
+        self.diagnostics
+            .extend(diagnostics.into_iter().map(|mut diagnostic| {
+                diagnostic.span = quote.source_span(&diagnostic.span);
+                diagnostic
+            }));
+
+        self.flush_text();
+        self.jump_bytes(end - start);
+        self.push_token(TokenKind::BlockQuote(quote), start);
+        self.text_start_byte = self.byte_pos;
+    }
+
+    /// 解析一行表格中的各单元格，`line_start` 为该行在输入中的位置
+    fn parse_table_row(&self, line_start: usize, line: &'a str) -> Vec<Vec<Token<'a>>> {
+        split_table_row(line)
+            .into_iter()
+            .map(|cell| {
+                let cell_start = line_start + cell.start;
+                let mut cell_parser = Parser::inline(&line[cell]);
+                cell_parser.parse();
+                let origin = self.lines.position(cell_start);
+                cell_parser
+                    .tokens
+                    .into_iter()
+                    .map(|token| token.relative_to(cell_start, origin))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// 跳过续行的缩进，`width` 为缩进的列数，超出所属列表项内容起始列的部分需要保留
+    fn push_list_indent(&mut self, level: usize, indent_len: usize, width: usize) {
+        let extra = width.saturating_sub(self.list_stack[level - 1].content_indent);
+        let start = self.byte_pos;
+        self.jump_bytes(indent_len);
+        self.push_token(TokenKind::ListIndent(level, extra), start);
         self.text_start_byte = self.byte_pos;
     }
 
-    fn parse_code(&mut self) {
+    /// 跳过 `len` 个字节，`len` 必须落在字符边界上
+    fn jump_bytes(&mut self, len: usize) {
+        let end = self.byte_pos + len;
+        while self.byte_pos < end {
+            self.jump_next_char();
+        }
+    }
+
+    fn flush_text(&mut self) {
+        if self.byte_pos > self.text_start_byte {
+            self.push_token(
+                TokenKind::Text(self.take_slice(self.text_start_byte, self.byte_pos)),
+                self.text_start_byte,
+            );
+        }
+    }
+
+    fn parse_math(&mut self) {
         self.flush_text();
+        let start = self.byte_pos;
         self.jump_next_char();
-        if self.current == Some('`') && self.peek_next() == Some('`') {
-            self.jump_next_char();
+        if self.current == Some('$') {
             self.jump_next_char();
             if !self.inside_quote_block {
-                self.parse_code_block();
+                self.parse_block_math(start);
             } else {
-                self.text_start_byte = self.byte_pos - 3; // 跳过 ```
+                self.text_start_byte = self.byte_pos - 2; // 跳过 $$
                 return;
             }
         } else {
-            self.parse_inline_code();
+            self.parse_inline_math(start);
         }
         self.text_start_byte = self.byte_pos;
     }
@@ -255,90 +922,309 @@ impl<'a> Parser<'a> {
             }
             self.jump_next_char();
         }
-        self.tokens
-            .push(Token::English(self.take_slice(start, self.byte_pos)));
+        if let Some(len) = url_len(&self.input[start..]) {
+            self.jump_bytes(start + len - self.byte_pos);
+            self.push_token(TokenKind::Url(self.take_slice(start, self.byte_pos)), start);
+        } else {
+            self.push_token(TokenKind::English(self.take_slice(start, self.byte_pos)), start);
+        }
+        self.text_start_byte = self.byte_pos;
+    }
+
+    /// 解析 `[文本](目标)`、`[文本][label]` 形式的链接与对应的图片，以及行首的链接引用定义。
+    /// 不是这些形式时，`[` 与 `!` 按普通文本处理
+    fn parse_link(&mut self, image: bool) {
+        let start = self.byte_pos;
+        let rest = &self.input[start..];
+        let line_begin = self.input[..start].rfind('\n').map_or(0, |i| i + 1);
+        if !image
+            && self.input[line_begin..start].trim().is_empty()
+            && let Some(len) = link_definition_len(rest)
+        {
+            self.flush_text();
+            self.jump_bytes(len);
+            self.push_token(TokenKind::LinkDefinition(self.take_slice(start, self.byte_pos)), start);
+            self.text_start_byte = self.byte_pos;
+            return;
+        }
+
+        let text_start = start + if image { 2 } else { 1 };
+        let Some(text_len) = closing_bracket(&self.input[text_start..]) else {
+            self.jump_next_char();
+            return;
+        };
+        let target_start = text_start + text_len + 1;
+        let after = &self.input[target_start..];
+        let target_len = match after.chars().next() {
+            Some('(') => closing_paren(after),
+            Some('[') => closing_bracket(&after[1..]).map(|len| len + 2),
+            _ => None,
+        };
+        let Some(target_len) = target_len else {
+            self.jump_next_char();
+            return;
+        };
+
+        self.flush_text();
+        let mut text_parser = Parser::inline(&self.input[text_start..text_start + text_len]);
+        text_parser.parse();
+        let origin = self.lines.position(text_start);
+        let text = text_parser
+            .tokens
+            .into_iter()
+            .map(|token| token.relative_to(text_start, origin))
+            .collect();
+        let target = self.take_slice(target_start, target_start + target_len);
+        self.jump_bytes(target_start + target_len - start);
+        self.push_token(TokenKind::Link(Link { image, text, target }), start);
+        self.text_start_byte = self.byte_pos;
+    }
+
+    /// 解析 `*强调*`、`**加粗**`、`~~删除线~~` 及对应的 `_` 形式，`***文本***` 视为加粗中的强调。
+    /// 开始与结束定界符需在同一行，开始定界符后、结束定界符前不能是空白，
+    /// `_` 不能出现在单词内部（如 `snake_case`）。找不到结束定界符时整段定界符按普通文本处理
+    fn parse_emphasis(&mut self, delimiter: char) {
+        let start = self.byte_pos;
+        let rest = &self.input[start..];
+        let run = rest.len() - rest.trim_start_matches(delimiter).len();
+        let before = self.input[..start].chars().next_back();
+        let after = rest[run..].chars().next();
+        let kind = match (delimiter, run) {
+            ('~', 2) => Some(EmphasisKind::Strikethrough),
+            ('~', _) => None,
+            (_, 1) => Some(EmphasisKind::Emphasis),
+            (_, 2 | 3) => Some(EmphasisKind::Strong),
+            _ => None,
+        };
+        let opens = after.is_some_and(|c| !c.is_whitespace())
+            && before != Some('\\')
+            && !(delimiter == '_' && before.is_some_and(char::is_alphanumeric));
+        let closing = kind
+            .filter(|_| opens)
+            .and_then(|_| closing_delimiter(&rest[run..], delimiter, run));
+        let Some((kind, closing)) = kind.zip(closing) else {
+            self.jump_bytes(run);
+            return;
+        };
+
+        self.flush_text();
+        // `***文本***` 的内容保留内层的一个定界符，由子解析器识别为强调
+        let marker_len = run.min(2);
+        let content_start = start + marker_len;
+        let content_end = start + run + closing + run - marker_len;
+        let end = start + run + closing + run;
+        let mut content_parser = Parser::inline(&self.input[content_start..content_end]);
+        content_parser.parse();
+        let origin = self.lines.position(content_start);
+        let content = content_parser
+            .tokens
+            .into_iter()
+            .map(|token| token.relative_to(content_start, origin))
+            .collect();
+        let word_after = self.input[end..].chars().next().is_some_and(char::is_alphanumeric);
+        self.jump_bytes(end - start);
+        self.push_token(
+            TokenKind::Emphasis(Emphasis {
+                kind,
+                delimiter,
+                word_before: before.is_some_and(char::is_alphanumeric),
+                word_after,
+                content,
+                source: self.take_slice(start, end),
+            }),
+            start,
+        );
+        self.text_start_byte = self.byte_pos;
+    }
+
+    /// 解析 `<https://example.com>`、`<foo@example.com>` 形式的链接，其它 `<` 按普通文本处理
+    fn parse_autolink(&mut self) {
+        let start = self.byte_pos;
+        let Some(len) = autolink_len(&self.input[start..]) else {
+            self.parse_inline_html();
+            return;
+        };
+        self.flush_text();
+        self.jump_bytes(len);
+        self.push_token(TokenKind::Autolink(self.take_slice(start, self.byte_pos)), start);
+        self.text_start_byte = self.byte_pos;
+    }
+
+    /// 解析行内的 HTML 标签、注释等，不是这些形式时 `<` 按普通文本处理
+    fn parse_inline_html(&mut self) {
+        let start = self.byte_pos;
+        let Some(len) = html_tag_len(&self.input[start..]) else {
+            self.jump_next_char();
+            return;
+        };
+        self.flush_text();
+        self.jump_bytes(len);
+        self.push_token(TokenKind::InlineHtml(self.take_slice(start, self.byte_pos)), start);
         self.text_start_byte = self.byte_pos;
     }
+
     fn peek_next(&self) -> Option<char> {
         let mut chars = self.chars.clone();
         chars.next()
     }
 
-    fn parse_inline_math(&mut self) {
-        let start = self.byte_pos;
+    fn parse_inline_math(&mut self, start: usize) {
+        let content_start = self.byte_pos;
         while let Some(c) = self.get_next_char() {
             if c == '$' {
-                self.tokens
-                    .push(Token::InlineMath(self.take_slice(start, self.byte_pos - 1)));
+                let content = self.take_slice(content_start, self.byte_pos - 1);
+                self.push_token(TokenKind::InlineMath(content), start);
                 return;
             }
         }
-        self.tokens.push(Token::InlineMath(
-            self.take_slice(start, self.byte_pos).trim(),
-        ));
+        let content = self.take_slice(content_start, self.byte_pos).trim();
+        self.push_token(TokenKind::InlineMath(content), start);
     }
 
-    fn parse_block_math(&mut self) {
-        let start = self.byte_pos;
+    fn parse_block_math(&mut self, start: usize) {
+        let content_start = self.byte_pos;
         while let Some(c) = self.get_next_char() {
             if c == '$' && self.peek() == Some('$') {
                 let end = self.byte_pos - 1;
                 self.jump_next_char(); // 消费第二个 $
-                self.tokens
-                    .push(Token::BlockMath(self.take_slice(start, end)));
+                let content = self.take_slice(content_start, end);
+                self.push_token(TokenKind::BlockMath(content), start);
                 return;
             }
         }
-        self.tokens
-            .push(Token::BlockMath(self.take_slice(start, self.byte_pos)));
+        let content = self.take_slice(content_start, self.byte_pos);
+        self.push_token(TokenKind::BlockMath(content), start);
+        self.diagnostics.push(Diagnostic {
+            span: self.lines.span(start..content_start),
+            message: "公式块缺少结束的 $$".to_string(),
+            kind: DiagnosticKind::UnclosedBlockMath,
+        });
     }
 
+    /// 解析行内代码：由若干个 ` 开始，到长度相同的一串 ` 结束；找不到时这串 ` 按普通文本处理
     fn parse_inline_code(&mut self) {
         let start = self.byte_pos;
-        while let Some(c) = self.get_next_char() {
-            if c == '`' {
-                self.tokens.push(Token::InlineCode(
-                    self.take_slice(start, self.byte_pos - 1).trim(),
-                ));
-                return;
+        let rest = &self.input[start..];
+        let ticks = rest.len() - rest.trim_start_matches('`').len();
+        let mut search = ticks;
+        let closing = loop {
+            let Some(offset) = rest[search..].find('`') else {
+                break None;
+            };
+            let run_start = search + offset;
+            let run = rest[run_start..].len() - rest[run_start..].trim_start_matches('`').len();
+            if run == ticks {
+                break Some(run_start);
             }
+            search = run_start + run;
+        };
+        let Some(closing) = closing else {
+            self.jump_bytes(ticks);
+            return;
+        };
+        self.flush_text();
+        let content = self.take_slice(start + ticks, start + closing).trim();
+        self.jump_bytes(closing + ticks);
+        self.push_token(TokenKind::InlineCode(content, ticks), start);
+        self.text_start_byte = self.byte_pos;
+    }
+
+    /// 当前位置之前，本行只有不超过 3 个空格的缩进，或者紧跟在列表项标记、续行缩进之后
+    fn at_block_start(&self) -> bool {
+        if self.inline || self.inside_quote_block {
+            return false;
         }
-        self.tokens
-            .push(Token::InlineCode(self.take_slice(start, self.byte_pos)));
+        let line_begin = self.input[..self.byte_pos].rfind('\n').map_or(0, |i| i + 1);
+        let before = &self.input[line_begin..self.byte_pos];
+        (before.len() <= 3 && before.chars().all(|c| c == ' '))
+            || (self.text_start_byte == self.byte_pos
+                && matches!(
+                    self.tokens.last().map(|token| &token.kind),
+                    Some(TokenKind::ListItem(_) | TokenKind::ListIndent(..))
+                ))
     }
 
-    fn parse_code_block(&mut self) {
-        let lang_start = self.byte_pos;
-        let mut lang_end = lang_start;
+    /// 解析围栏代码块。围栏由至少 3 个 ` 或 ~ 组成，到由相同字符组成、长度不短于开始围栏的
+    /// 一行为止，因此 ```` 围栏中可以包含 ``` 的示例；没有结束围栏时延续到文档末尾
+    fn parse_code_block(&mut self, fence_len: usize) {
+        self.flush_text();
+        let start = self.byte_pos;
+        let indent = match self.tokens.last().map(|token| &token.kind) {
+            Some(TokenKind::ListItem(_)) => self.list_stack.last().map_or(0, |item| item.content_indent),
+            Some(TokenKind::ListIndent(level, extra)) => self.list_stack[level - 1].content_indent + extra,
+            _ => 0,
+        };
+        let fence = self.take_slice(start, start + fence_len);
+        let lang_start = start + fence_len;
+        let lang_end = self.input[lang_start..]
+            .find('\n')
+            .map_or(self.input.len(), |i| lang_start + i);
+        // 信息字符串的第一个词是语言，其后的 `,ignore`、` title="main.py" {1,3}` 等是属性
+        let info = self.take_slice(lang_start, lang_end).trim();
+        let lang_len = info
+            .find(|c: char| c.is_whitespace() || c == ',' || c == '{')
+            .unwrap_or(info.len());
+        let tag = &info[..lang_len];
+        let lang = normalize_language(tag);
+        let attributes = &info[lang_len..];
+        let content_start = (lang_end + 1).min(self.input.len());
 
-        // 解析语言标识符
-        while let Some(c) = self.get_next_char() {
-            if c == '\n' {
-                lang_end = self.byte_pos - 1;
+        // 列表项中的围栏随列表项缩进
+        let max_indent = self.list_stack.last().map_or(0, |item| item.content_indent) + 3;
+        let mut line_start = content_start;
+        let mut closing = None;
+        for line in self.input[content_start..].split('\n') {
+            let trimmed = line.trim_start_matches(' ');
+            if line.len() - trimmed.len() <= max_indent && is_closing_fence(trimmed, fence) {
+                closing = Some((line_start, line_start + line.trim_end().len()));
                 break;
             }
+            line_start += line.len() + 1;
         }
-        let lang = normalize_language(self.take_slice(lang_start, lang_end).trim());
-        let content_start = self.byte_pos;
-        while let Some(c) = self.get_next_char() {
-            if c == '`' && self.peek_next() == Some('`') && self.chars.clone().nth(0) == Some('`') {
-                let content_end = self.byte_pos - 1;
-                self.jump_next_char(); // 消费第二个 `
-                self.jump_next_char(); // 消费第三个 `
-                self.code_blocks.push(CodeBlock {
-                    language: lang,
-                    content: self.take_slice(content_start, content_end),
-                });
-                self.tokens.push(Token::FakeCodeBlock); // 占位符
-                return;
-            }
-        }
+
+        let (content_end, end) = closing.unwrap_or((self.input.len(), self.input.len()));
         self.code_blocks.push(CodeBlock {
             language: lang,
-            content: self.take_slice(content_start, self.byte_pos),
+            tag,
+            attributes,
+            fence,
+            content: self.take_slice(content_start, content_end),
+            indent,
+            span: self.lines.span(start..end),
+            content_span: self.lines.span(content_start..content_end),
         });
+        if closing.is_none() {
+            // 未闭合的代码块会一直延续到文档末尾，只标出开头的围栏行
+            self.diagnostics.push(Diagnostic {
+                span: self.lines.span(start..lang_end),
+                message: format!("代码块缺少结束的 {}", fence),
+                kind: DiagnosticKind::UnclosedCodeBlock,
+            });
+        }
+        self.jump_bytes(end - start);
+        self.push_token(TokenKind::FakeCodeBlock, start); // 占位符
+        self.text_start_byte = self.byte_pos;
+    }
 
-        self.tokens.push(Token::FakeCodeBlock); // 占位符
+    /// 从当前行开始解析缩进代码块：连续的缩进至少 4 列的行，中间可以有空行
+    fn parse_indented_code(&mut self) {
+        let start = self.byte_pos;
+        let mut end = start;
+        let mut line_start = start;
+        for line in self.input[start..].split('\n') {
+            let content = line.trim_start_matches([' ', '\t']);
+            if !content.trim().is_empty() {
+                if indent_width(&line[..line.len() - content.len()]) < 4 {
+                    break;
+                }
+                end = line_start + line.len();
+            }
+            line_start += line.len() + 1;
+        }
+        self.flush_text();
+        self.jump_bytes(end - start);
+        self.push_token(TokenKind::IndentedCode(self.take_slice(start, end)), start);
+        self.text_start_byte = self.byte_pos;
     }
 
     fn parse_number(&mut self) {
@@ -350,8 +1236,7 @@ impl<'a> Parser<'a> {
             }
             self.jump_next_char();
         }
-        self.tokens
-            .push(Token::Number(self.take_slice(start, self.byte_pos)));
+        self.push_token(TokenKind::Number(self.take_slice(start, self.byte_pos)), start);
         self.text_start_byte = self.byte_pos;
     }
 
@@ -364,12 +1249,489 @@ impl<'a> Parser<'a> {
             }
             self.jump_next_char();
         }
-        self.tokens
-            .push(Token::Chinese(self.take_slice(start, self.byte_pos)));
+        self.push_token(TokenKind::Chinese(self.take_slice(start, self.byte_pos)), start);
         self.text_start_byte = self.byte_pos;
     }
 }
 
+impl<'a> Token<'a> {
+    /// 将嵌套解析得到的 token（及其子 token）的范围换算为外层输入中的范围
+    fn relative_to(self, origin_byte: usize, origin: Position) -> Token<'a> {
+        let kind = match self.kind {
+            TokenKind::Title(tokens, level) => TokenKind::Title(
+                tokens
+                    .into_iter()
+                    .map(|token| token.relative_to(origin_byte, origin))
+                    .collect(),
+                level,
+            ),
+            TokenKind::Link(link) => TokenKind::Link(Link {
+                text: link
+                    .text
+                    .into_iter()
+                    .map(|token| token.relative_to(origin_byte, origin))
+                    .collect(),
+                ..link
+            }),
+            TokenKind::Emphasis(emphasis) => TokenKind::Emphasis(Emphasis {
+                content: emphasis
+                    .content
+                    .into_iter()
+                    .map(|token| token.relative_to(origin_byte, origin))
+                    .collect(),
+                ..emphasis
+            }),
+            kind => kind,
+        };
+        Token {
+            kind,
+            span: self.span.relative_to(origin_byte, origin),
+        }
+    }
+}
+
+/// `<!-- rustdown-ignore-... -->` 形式的忽略指令
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IgnoreDirective {
+    Start,
+    End,
+    NextLine,
+}
+
+/// 整行只有一条忽略指令时返回该指令
+fn ignore_directive(line: &str) -> Option<IgnoreDirective> {
+    let comment = line.trim().strip_prefix("<!--")?.strip_suffix("-->")?;
+    match comment.trim() {
+        "rustdown-ignore-start" => Some(IgnoreDirective::Start),
+        "rustdown-ignore-end" => Some(IgnoreDirective::End),
+        "rustdown-ignore-next-line" => Some(IgnoreDirective::NextLine),
+        _ => None,
+    }
+}
+
+/// 行是代码块或公式块的开头时，返回其开始围栏
+fn block_fence(line: &str) -> Option<&str> {
+    let line = line.trim();
+    if line == "$$" {
+        Some(line)
+    } else {
+        fence_len(line).map(|len| &line[..len])
+    }
+}
+
+/// `text` 以代码块的开始围栏开头时返回围栏的长度：至少 3 个 ` 或 ~，
+/// ` 组成的围栏所在行的其余部分不能再含有 `
+fn fence_len(text: &str) -> Option<usize> {
+    let fence_char = text.chars().next().filter(|c| matches!(c, '`' | '~'))?;
+    let len = text.len() - text.trim_start_matches(fence_char).len();
+    let line = &text[len..text.find('\n').unwrap_or(text.len())];
+    (len >= 3 && !(fence_char == '`' && line.contains('`'))).then_some(len)
+}
+
+/// 去掉缩进后的 `line` 是否是 `fence` 的结束围栏：相同的字符，不短于开始围栏，之后只有空白
+fn is_closing_fence(line: &str, fence: &str) -> bool {
+    let Some(fence_char) = fence.chars().next() else {
+        return false;
+    };
+    let len = line.len() - line.trim_start_matches(fence_char).len();
+    len >= fence.len() && line[len..].trim().is_empty()
+}
+
+/// 内容原样保留、直到出现对应结束标签为止的 HTML 块
+const HTML_RAW_TAGS: [&str; 4] = ["script", "pre", "style", "textarea"];
+
+/// 开头一行就能构成 HTML 块的块级标签，对应 CommonMark 中的第 6 类 HTML 块
+const HTML_BLOCK_TAGS: [&str; 62] = [
+    "address", "article", "aside", "base", "basefont", "blockquote", "body", "caption", "center",
+    "col", "colgroup", "dd", "details", "dialog", "dir", "div", "dl", "dt", "fieldset",
+    "figcaption", "figure", "footer", "form", "frame", "frameset", "h1", "h2", "h3", "h4", "h5",
+    "h6", "head", "header", "hr", "html", "iframe", "legend", "li", "link", "main", "menu",
+    "menuitem", "nav", "noframes", "ol", "optgroup", "option", "p", "param", "search", "section",
+    "summary", "table", "tbody", "td", "tfoot", "th", "thead", "title", "tr", "track", "ul",
+];
+
+/// HTML 块的结束条件
+#[derive(Debug, Clone, Copy)]
+enum HtmlBlockEnd {
+    /// 到含有其中任一字符串（小写）的一行为止，该行属于 HTML 块
+    Contains(&'static [&'static str]),
+    /// 到空行之前为止
+    BlankLine,
+}
+
+/// 以 `line` 开头的行是否开始一个 HTML 块，返回其结束条件。
+/// `after_blank` 为 false 时只有块级标签等才能开始 HTML 块，单独一行的任意标签不能打断段落
+fn html_block_start(line: &str, after_blank: bool) -> Option<HtmlBlockEnd> {
+    let lower = line.to_ascii_lowercase();
+    let rest = lower.strip_prefix('<')?;
+    let tag = rest.strip_prefix('/').unwrap_or(rest);
+    let name = &tag[..tag_name_len(tag).unwrap_or(0)];
+    let after_name = &tag[name.len()..];
+
+    if HTML_RAW_TAGS.contains(&name)
+        && !rest.starts_with('/')
+        && (after_name.is_empty() || after_name.starts_with([' ', '\t', '\r', '>']))
+    {
+        Some(HtmlBlockEnd::Contains(&["</script>", "</pre>", "</style>", "</textarea>"]))
+    } else if rest.starts_with("!--") {
+        Some(HtmlBlockEnd::Contains(&["-->"]))
+    } else if rest.starts_with('?') {
+        Some(HtmlBlockEnd::Contains(&["?>"]))
+    } else if rest.starts_with("![cdata[") {
+        Some(HtmlBlockEnd::Contains(&["]]>"]))
+    } else if rest.starts_with('!') && rest[1..].starts_with(|c: char| c.is_ascii_alphabetic()) {
+        Some(HtmlBlockEnd::Contains(&[">"]))
+    } else if (HTML_BLOCK_TAGS.contains(&name)
+        && (after_name.trim().is_empty()
+            || after_name.starts_with([' ', '\t', '>'])
+            || after_name.starts_with("/>")))
+        || (after_blank
+            && !HTML_RAW_TAGS.contains(&name)
+            && html_tag_len(line).is_some_and(|len| line[len..].trim().is_empty()))
+    {
+        Some(HtmlBlockEnd::BlankLine)
+    } else {
+        None
+    }
+}
+
+/// `text` 以 `<` 开头时，返回行内 HTML（开始或结束标签、注释、处理指令、声明、CDATA）的长度。
+/// 只在当前行内查找；普通标签只扫描标签本身，不会为每个 `<` 扫描到行尾
+pub(crate) fn html_tag_len(text: &str) -> Option<usize> {
+    let rest = text.strip_prefix('<')?;
+    for (open, close) in [("!--", "-->"), ("?", "?>"), ("![CDATA[", "]]>")] {
+        if let Some(body) = rest.strip_prefix(open) {
+            let line = &body[..body.find('\n').unwrap_or(body.len())];
+            return line.find(close).map(|i| 1 + open.len() + i + close.len());
+        }
+    }
+    if let Some(body) = rest.strip_prefix('!') {
+        if !body.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            return None;
+        }
+        let end = body.find(['>', '\n'])?;
+        return body[end..].starts_with('>').then_some(2 + end + 1);
+    }
+    let skip_spaces = |i: usize| text.len() - text[i..].trim_start_matches([' ', '\t']).len();
+    if let Some(body) = rest.strip_prefix('/') {
+        let end = skip_spaces(2 + tag_name_len(body)?);
+        return text[end..].starts_with('>').then_some(end + 1);
+    }
+
+    // 开始标签：标签名之后是若干以空白分隔的属性，属性值可以带引号
+    let mut i = 1 + tag_name_len(rest)?;
+    loop {
+        let spaced = skip_spaces(i);
+        let rest = &text[spaced..];
+        if rest.starts_with('>') {
+            return Some(spaced + 1);
+        }
+        if rest.starts_with("/>") {
+            return Some(spaced + 2);
+        }
+        if spaced == i || !rest.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_' || c == ':')
+        {
+            return None;
+        }
+        i = spaced
+            + rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | ':' | '-')))
+                .unwrap_or(rest.len());
+        let equals = skip_spaces(i);
+        if text[equals..].starts_with('=') {
+            let value_start = skip_spaces(equals + 1);
+            let value = &text[value_start..];
+            let value_len = match value.chars().next()? {
+                quote @ ('"' | '\'') => {
+                    let end = 1 + value[1..].find([quote, '\n'])?;
+                    if !value[end..].starts_with(quote) {
+                        return None;
+                    }
+                    end + 1
+                }
+                _ => match value.find(|c: char| {
+                    c.is_whitespace() || matches!(c, '"' | '\'' | '=' | '<' | '>' | '`')
+                }) {
+                    Some(0) => return None,
+                    Some(len) => len,
+                    None => value.len(),
+                },
+            };
+            i = value_start + value_len;
+        }
+    }
+}
+
+/// 标签名由字母开头，之后是字母、数字或 `-`
+fn tag_name_len(text: &str) -> Option<usize> {
+    if !text.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    Some(text.find(|c: char| !(c.is_ascii_alphanumeric() || c == '-')).unwrap_or(text.len()))
+}
+
+/// `text` 以开始定界符之后的内容开头，返回由 `len` 个 `delimiter` 组成的结束定界符的位置。
+/// 长度不同的定界符属于嵌套的强调，行内代码中的定界符不算；强调不跨行
+fn closing_delimiter(text: &str, delimiter: char, len: usize) -> Option<usize> {
+    let mut prev = None;
+    let mut i = 0;
+    while let Some(c) = text[i..].chars().next() {
+        match c {
+            '\n' => return None,
+            '\\' => {
+                // 跳过被转义的字符
+                i += 1;
+                if let Some(escaped) = text[i..].chars().next() {
+                    i += escaped.len_utf8();
+                }
+                prev = Some(c);
+                continue;
+            }
+            '`' => {
+                let ticks = text[i..].len() - text[i..].trim_start_matches('`').len();
+                let fence = &text[i..i + ticks];
+                let line_end = text[i..].find('\n').map_or(text.len(), |n| i + n);
+                if let Some(close) = text[i + ticks..line_end].find(fence) {
+                    i += ticks + close + ticks;
+                    prev = Some('`');
+                    continue;
+                }
+            }
+            c if c == delimiter => {
+                let run = text[i..].len() - text[i..].trim_start_matches(delimiter).len();
+                let after = text[i + run..].chars().next();
+                if run == len
+                    && prev.is_some_and(|p: char| !p.is_whitespace())
+                    && !(delimiter == '_' && after.is_some_and(char::is_alphanumeric))
+                {
+                    return Some(i);
+                }
+                i += run;
+                prev = Some(c);
+                continue;
+            }
+            _ => {}
+        }
+        prev = Some(c);
+        i += c.len_utf8();
+    }
+    None
+}
+
+/// `text` 以 `[` 之后的内容开头，返回与之配对的 `]` 的位置；链接文本不跨行
+fn closing_bracket(text: &str) -> Option<usize> {
+    let mut depth = 1;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            '\n' => return None,
+            '[' if !escaped => depth += 1,
+            ']' if !escaped => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+        escaped = c == '\\' && !escaped;
+    }
+    None
+}
+
+/// `text` 以 `(` 开头，返回到与之配对的 `)` 为止（含括号）的长度；链接目标不跨行
+fn closing_paren(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            '\n' => return None,
+            '(' if !escaped => depth += 1,
+            ')' if !escaped => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+            _ => {}
+        }
+        escaped = c == '\\' && !escaped;
+    }
+    None
+}
+
+/// `text` 以 `[label]:` 开头时返回整行（不含换行符）的长度；`[^1]:` 是脚注，不算在内
+fn link_definition_len(text: &str) -> Option<usize> {
+    let label_len = closing_bracket(&text[1..])?;
+    let label = &text[1..1 + label_len];
+    if label.trim().is_empty() || label.starts_with('^') || !text[label_len + 2..].starts_with(':') {
+        return None;
+    }
+    let line = &text[..text.find('\n').unwrap_or(text.len())];
+    Some(line.trim_end_matches('\r').len())
+}
+
+/// `text` 以 `<` 开头时，返回 `<scheme:...>` 或 `<邮箱>` 形式的链接（含尖括号）的长度。
+/// 链接中不能有空白与 `<`，因此只需查找到其中第一个为止，不会扫描到行外
+fn autolink_len(text: &str) -> Option<usize> {
+    let end = 1 + text[1..].find(|c: char| c == '>' || c == '<' || c.is_whitespace())?;
+    if !text[end..].starts_with('>') {
+        return None;
+    }
+    let inner = &text[1..end];
+    if inner.is_empty() {
+        return None;
+    }
+    let is_uri = inner.split_once(':').is_some_and(|(scheme, _)| {
+        (2..=32).contains(&scheme.len())
+            && scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+            && scheme
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '.' | '-'))
+    });
+    let is_email = inner.split_once('@').is_some_and(|(user, domain)| {
+        !user.is_empty() && domain.contains('.') && !domain.contains('@')
+    });
+    (is_uri || is_email).then_some(end + 1)
+}
+
+/// `text` 以 `http://`、`https://`、`ftp://` 或 `www.` 开头时返回裸链接的长度。
+/// 链接在空白、`<` 与中文标点处结束，末尾的 `.`、`,` 等标点与不配对的 `)` 不算在内
+fn url_len(text: &str) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let prefix = ["http://", "https://", "ftp://", "www."]
+        .into_iter()
+        .find(|prefix| {
+            bytes.len() >= prefix.len() && bytes[..prefix.len()].eq_ignore_ascii_case(prefix.as_bytes())
+        })?;
+    let end = text
+        .find(|c: char| c.is_whitespace() || c == '<' || is_cjk_punctuation(c))
+        .unwrap_or(text.len());
+    let mut url = &text[..end];
+    loop {
+        let trimmed = url.trim_end_matches(['?', '!', '.', ',', ':', ';', '*', '_', '~', '\'', '"']);
+        let trimmed = match trimmed.strip_suffix(')') {
+            // 末尾不配对的 `)` 通常是包住链接的括号
+            Some(inner) if trimmed.matches('(').count() < trimmed.matches(')').count() => inner,
+            _ => trimmed,
+        };
+        if trimmed.len() == url.len() {
+            break;
+        }
+        url = trimmed;
+    }
+    // 只有前缀的不算链接
+    (url.len() > prefix.len()).then_some(url.len())
+}
+
+/// 中文标点与全角符号
+fn is_cjk_punctuation(c: char) -> bool {
+    matches!(c, '\u{3000}'..='\u{303f}' | '\u{ff00}'..='\u{ffef}')
+}
+
+/// 缩进的列宽，制表符对齐到 4 的倍数
+fn indent_width(indent: &str) -> usize {
+    indent.chars().fold(0, |width, c| match c {
+        '\t' => width + 4 - width % 4,
+        _ => width + 1,
+    })
+}
+
+/// 识别行首（已去掉缩进）的列表项标记，返回标记及其字节长度
+fn parse_list_marker(line: &str) -> Option<(ListMarker, usize)> {
+    let first = line.chars().next()?;
+    let (marker, len) = if matches!(first, '-' | '*' | '+') {
+        (ListMarker::Bullet(first), 1)
+    } else {
+        let digits = line.len() - line.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        if digits == 0 || digits > 9 {
+            return None;
+        }
+        let delimiter = line[digits..].chars().next()?;
+        if delimiter != '.' && delimiter != ')' {
+            return None;
+        }
+        let number = line[..digits].parse().ok()?;
+        (ListMarker::Ordered { number, delimiter }, digits + 1)
+    };
+    // 标记后必须是空白或行尾
+    match line[len..].chars().next() {
+        None | Some(' ' | '\t' | '\r') => Some((marker, len)),
+        _ => None,
+    }
+}
+
+/// 打断段落的列表项才能开始一个新列表：有序列表只能从 1 开始，且不能是空列表项，
+/// 否则像 `今年是\n2024. 很好的一年` 这样的正文会被误认为列表
+fn can_interrupt_paragraph(marker: ListMarker, after_marker: &str) -> bool {
+    match marker {
+        ListMarker::Bullet(_) => true,
+        ListMarker::Ordered { number, .. } => number == 1 && !after_marker.trim().is_empty(),
+    }
+}
+
+/// 把表格的一行按未转义的 `|` 拆分为单元格，返回各单元格去掉首尾空白后在行内的字节范围
+fn split_table_row(line: &str) -> Vec<Range<usize>> {
+    let mut start = line.len() - line.trim_start().len();
+    // 只有空白的行 trim 之后起点会越过终点
+    let mut end = line.trim_end().len().max(start);
+    if line[start..end].starts_with('|') {
+        start += 1;
+    }
+    if end > start && line[start..end].ends_with('|') && !line[start..end].ends_with("\\|") {
+        end -= 1;
+    }
+
+    let mut cells = Vec::new();
+    let mut cell_start = start;
+    let mut escaped = false;
+    for (i, c) in line[start..end].char_indices() {
+        if c == '|' && !escaped {
+            cells.push(cell_start..start + i);
+            cell_start = start + i + 1;
+        }
+        escaped = c == '\\' && !escaped;
+    }
+    cells.push(cell_start..end);
+
+    cells
+        .into_iter()
+        .map(|cell| {
+            let text = &line[cell.clone()];
+            let leading = text.len() - text.trim_start().len();
+            cell.start + leading..cell.start + leading + text.trim().len()
+        })
+        .collect()
+}
+
+/// 解析分隔行中的一个单元格，不是 `:?-+:?` 的形式时返回 `None`
+fn parse_alignment(cell: &str) -> Option<Alignment> {
+    let left = cell.starts_with(':');
+    let right = cell.len() > 1 && cell.ends_with(':');
+    let dashes = &cell[left as usize..cell.len() - right as usize];
+    if dashes.is_empty() || !dashes.chars().all(|c| c == '-') {
+        return None;
+    }
+    Some(match (left, right) {
+        (false, false) => Alignment::None,
+        (true, false) => Alignment::Left,
+        (true, true) => Alignment::Center,
+        (false, true) => Alignment::Right,
+    })
+}
+
+/// `---`、`* * *` 等分隔线，与无序列表标记相似但不是列表项
+fn is_thematic_break(line: &str) -> bool {
+    let mut marks = line.chars().filter(|c| !c.is_whitespace());
+    let Some(first) = marks.next() else {
+        return false;
+    };
+    matches!(first, '-' | '*' | '_') && {
+        let rest: Vec<char> = marks.collect();
+        rest.len() >= 2 && rest.iter().all(|&c| c == first)
+    }
+}
+
 #[inline]
 fn is_chinese(c: char) -> bool {
     matches!(c, '\u{4e00}'..='\u{9fff}')
@@ -395,3 +1757,245 @@ fn normalize_language(lang: &str) -> &str {
         _ => lang,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> Parser<'_> {
+        let mut parser = Parser::new(input);
+        parser.parse();
+        parser
+    }
+
+    #[test]
+    fn block_quote_keeps_nested_tokens() {
+        let parser = parse("> a\n> > b\n");
+        let [Token { kind: TokenKind::BlockQuote(quote), .. }, ..] = &parser.get_tokens()[..] else {
+            panic!("应解析为引用块");
+        };
+        assert_eq!(quote.content(), "a\n> b");
+        let content = quote.tokens().unwrap();
+        assert!(
+            content
+                .tokens
+                .iter()
+                .any(|token| matches!(token.kind, TokenKind::BlockQuote(_)))
+        );
+    }
+
+    /// 解析出的引用块最多嵌套几层
+    fn quote_depth(tokens: &[Token]) -> usize {
+        tokens
+            .iter()
+            .filter_map(|token| match &token.kind {
+                TokenKind::BlockQuote(quote) => {
+                    Some(1 + quote.tokens().map_or(0, |content| quote_depth(&content.tokens)))
+                }
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    #[test]
+    fn block_quote_depth_is_limited() {
+        let input = "> ".repeat(3000) + "x";
+        // 超过上限的那一层不再解析
+        assert_eq!(quote_depth(parse(&input).get_tokens()), MAX_QUOTE_DEPTH + 1);
+    }
+
+    fn has_list_item(input: &str) -> bool {
+        parse(input)
+            .get_tokens()
+            .iter()
+            .any(|token| matches!(token.kind, TokenKind::ListItem(_)))
+    }
+
+    #[test]
+    fn ordered_marker_after_paragraph_is_text() {
+        assert!(!has_list_item("今年是\n2024. 很好的一年"));
+        assert!(!has_list_item("版本号\n3. 不是列表"));
+        assert!(!has_list_item("段落\n1.\n"));
+        assert!(has_list_item("段落\n1. 列表"));
+        assert!(has_list_item("段落\n\n3. 列表"));
+        assert!(has_list_item("# 标题\n3. 列表"));
+        assert!(has_list_item("1. a\n2. b"));
+    }
+
+    #[test]
+    fn autolinks() {
+        assert_eq!(autolink_len("<https://example.com/路径> 后文"), Some("<https://example.com/路径>".len()));
+        assert_eq!(autolink_len("<mailto:a@b.c>"), Some(14));
+        assert_eq!(autolink_len("<a@example.com>"), Some(15));
+        assert_eq!(autolink_len("<https://a b>"), None);
+        assert_eq!(autolink_len("<kbd>"), None);
+        assert_eq!(autolink_len("<https://a\n>"), None);
+        assert_eq!(autolink_len("<>"), None);
+    }
+
+    #[test]
+    fn bare_urls_stop_at_cjk_punctuation() {
+        assert_eq!(url_len("https://例子.com/路径/文档。后文"), Some("https://例子.com/路径/文档".len()));
+        assert_eq!(url_len("https://a.com/x，以及"), Some(15));
+        assert_eq!(url_len("https://a.com/x). 后文"), Some(15));
+        assert_eq!(url_len("https://a.com/(x) 后文"), Some(17));
+        assert_eq!(url_len("www.a.com."), Some(9));
+    }
+
+    #[test]
+    fn links_with_nested_brackets() {
+        let parser = parse("见[文档 [v2]](https://a.com/(x)) 后文");
+        let link = parser
+            .get_tokens()
+            .iter()
+            .find_map(|token| match &token.kind {
+                TokenKind::Link(link) => Some((link, token.span.bytes.clone())),
+                _ => None,
+            })
+            .expect("应解析为链接");
+        assert_eq!(link.1, "见".len().."见[文档 [v2]](https://a.com/(x))".len());
+        assert!(!has_link("[未闭合](https://a.com"));
+    }
+
+    fn has_link(input: &str) -> bool {
+        parse(input)
+            .get_tokens()
+            .iter()
+            .any(|token| matches!(token.kind, TokenKind::Link(_)))
+    }
+
+    #[test]
+    fn images_are_links() {
+        assert!(has_link("![图片](a.png)"));
+    }
+
+    #[test]
+    fn triple_delimiters_are_strong_around_emphasis() {
+        let parser = parse("***a***");
+        let [Token { kind: TokenKind::Emphasis(outer), .. }] = &parser.get_tokens()[..] else {
+            panic!("应解析为加粗");
+        };
+        assert_eq!(outer.kind, EmphasisKind::Strong);
+        assert_eq!(outer.source, "***a***");
+        let [Token { kind: TokenKind::Emphasis(inner), .. }] = &outer.content[..] else {
+            panic!("内层应解析为强调");
+        };
+        assert_eq!(inner.kind, EmphasisKind::Emphasis);
+    }
+
+    #[test]
+    fn underscore_inside_words_is_text() {
+        let parser = parse("snake_case_name");
+        assert!(
+            !parser
+                .get_tokens()
+                .iter()
+                .any(|token| matches!(token.kind, TokenKind::Emphasis(_)))
+        );
+    }
+
+    #[test]
+    fn url_prefix_is_case_insensitive() {
+        assert_eq!(url_len("HTTPS://example.com 后面"), Some(19));
+        assert_eq!(url_len("www.例子.com"), Some(14));
+        assert_eq!(url_len("中文http://a"), None);
+        assert_eq!(url_len("http"), None);
+    }
+
+    #[test]
+    fn table_alignments_and_escaped_pipes() {
+        let parser = parse("| a\\|b | c | d | e |\n|---|:--|:-:|--:|\n| 1 | 2 | 3 | 4 |\n");
+        let [Token { kind: TokenKind::Table(table), .. }, ..] = &parser.get_tokens()[..] else {
+            panic!("应解析为表格");
+        };
+        assert_eq!(
+            table.alignments,
+            [Alignment::None, Alignment::Left, Alignment::Center, Alignment::Right]
+        );
+        assert_eq!(table.rows.len(), 2);
+        assert!(table.rows.iter().all(|row| row.len() == 4));
+        assert_eq!(split_table_row("| a\\|b | c |"), vec![2..6, 9..10]);
+    }
+
+    #[test]
+    fn delimiter_row_must_match_header() {
+        let is_table = |input| {
+            parse(input)
+                .get_tokens()
+                .iter()
+                .any(|token| matches!(token.kind, TokenKind::Table(_)))
+        };
+        assert!(is_table("a | b\n--|--\n"));
+        assert!(!is_table("a | b\n--|--|--\n"));
+        assert!(!is_table("a | b\nc | d\n"));
+    }
+
+    #[test]
+    fn table_row_followed_by_blank_line() {
+        assert_eq!(split_table_row(" "), vec![1..1]);
+        for input in ["a | b\r\n\r\nc\r\n", "a | b\n \nc\n"] {
+            let parser = parse(input);
+            assert!(
+                !parser
+                    .get_tokens()
+                    .iter()
+                    .any(|token| matches!(token.kind, TokenKind::Table(_)))
+            );
+        }
+    }
+
+    fn html_blocks(input: &str) -> Vec<&str> {
+        parse(input)
+            .get_tokens()
+            .iter()
+            .filter_map(|token| match token.kind {
+                TokenKind::HtmlBlock(html) => Some(html),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn inline_html(input: &str) -> Vec<&str> {
+        parse(input)
+            .get_tokens()
+            .iter()
+            .filter_map(|token| match token.kind {
+                TokenKind::InlineHtml(html) => Some(html),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn html_block_ends_at_blank_line() {
+        assert_eq!(html_blocks("<div>\n中文 *a*\n</div>\n\n中文\n"), ["<div>\n中文 *a*\n</div>"]);
+        assert_eq!(html_blocks("<div>\na\n\nb\n</div>\n"), ["<div>\na", "</div>"]);
+        // 单独一行的任意标签只在空行之后开始 HTML 块，不能打断段落
+        assert_eq!(html_blocks("<span>\na\n"), ["<span>\na"]);
+        assert!(html_blocks("a\n<span>\n").is_empty());
+        assert_eq!(html_blocks("a\n<div>\n"), ["<div>"]);
+    }
+
+    #[test]
+    fn html_comment_block_spans_blank_lines() {
+        assert_eq!(html_blocks("<!--\na\n\nb\n-->\nc\n"), ["<!--\na\n\nb\n-->"]);
+        assert_eq!(
+            html_blocks("<pre>\na\n\n  b\n</pre>\nc\n"),
+            ["<pre>\na\n\n  b\n</pre>"]
+        );
+    }
+
+    #[test]
+    fn inline_html_tags() {
+        assert_eq!(
+            inline_html("按 <kbd>Ctrl</kbd>键和<br/>复制\n"),
+            ["<kbd>", "</kbd>", "<br/>"]
+        );
+        assert_eq!(inline_html("中文<!-- 注释 a  b -->english\n"), ["<!-- 注释 a  b -->"]);
+        assert_eq!(inline_html("<a href=\"x>y\">链接</a>\n"), ["<a href=\"x>y\">", "</a>"]);
+        // 不成立的标签是普通文本
+        assert!(inline_html("1 < 2 > 0, a <b c\n").is_empty());
+        assert!(inline_html("a <!-- 未闭合\n-->\n").is_empty());
+    }
+}
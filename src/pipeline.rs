@@ -1,27 +1,226 @@
-use crate::{config::Config, parser::Parser, formatter::Formatter};
+use crate::{
+    config::Config,
+    diagnostic::Diagnostic,
+    error::Error,
+    formatter::Formatter,
+    parser::{ListItem, Parser, Token, TokenKind},
+    span::LineIndex,
+};
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
 
-pub fn format_string(input: &str, config: &Config)->String{
-    #[cfg(debug_assertions)]
-    let t1 = std::time::Instant::now();
+/// 格式化结果
+#[derive(Debug, Clone)]
+pub struct Formatted {
+    /// 格式化后的完整文档
+    pub output: String,
+    /// 格式化是否改变了输入
+    pub changed: bool,
+    /// 格式化过程中发现的问题，例如外部代码格式化工具运行失败
+    pub diagnostics: Vec<Diagnostic>,
+}
 
+/// 格式化一篇 markdown 文档。
+/// 不会读写文件系统，也不会向 stdout 输出任何内容；
+/// 内部错误（包括依赖库的 panic）以 `Err` 返回，而不会让调用方崩溃。
+/// 这要求以 `panic = "unwind"`（默认值）编译；panic 信息仍由进程的 panic hook 处理，
+/// 默认会打印到 stderr，需要完全静默的调用方可以用 `std::panic::set_hook` 替换。
+pub fn format(input: &str, config: &Config) -> Result<Formatted, Error> {
+    let (output, diagnostics) = catch_internal(|| format_string(input, config))?;
+    Ok(Formatted {
+        changed: output != input,
+        output,
+        diagnostics,
+    })
+}
+
+/// 只格式化与 `range`（输入中的字节范围）相交的顶层块，文档的其余部分逐字节保持不变。
+/// 顶层块指被空行分隔开的段落、标题、代码块、公式块等；`range` 为空时格式化它所在的块，
+/// 落在块之间的空行中时不做任何修改。诊断信息的位置仍相对于整个输入。
+pub fn format_range(input: &str, range: Range<usize>, config: &Config) -> Result<Formatted, Error> {
+    if range.start > range.end || range.end > input.len() {
+        return Err(Error::InvalidRange {
+            start: range.start,
+            end: range.end,
+            len: input.len(),
+        });
+    }
+    let (output, diagnostics) = catch_internal(|| format_range_string(input, range, config))?;
+    Ok(Formatted {
+        changed: output != input,
+        output,
+        diagnostics,
+    })
+}
+
+/// 只解析而不格式化，返回输入中的结构问题，例如未闭合的代码块。
+/// 不会运行外部代码格式化工具，适合在编辑器中随输入实时调用。
+pub fn diagnose(input: &str) -> Vec<Diagnostic> {
     let mut parser = Parser::new(input);
     parser.parse();
+    parser.get_diagnostics().clone()
+}
 
-    #[cfg(debug_assertions)]
-    let t2 = std::time::Instant::now();
+/// 执行 `f`，把其中的 panic 转为 `Error::Internal`
+fn catch_internal<T>(f: impl FnOnce() -> T) -> Result<T, Error> {
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "未知错误".to_string());
+        Error::Internal(message)
+    })
+}
+
+pub(crate) fn format_string(input: &str, config: &Config) -> (String, Vec<Diagnostic>) {
+    format_parsed(Parser::new(input), config)
+}
+
+fn format_parsed(mut parser: Parser, config: &Config) -> (String, Vec<Diagnostic>) {
+    parser.parse();
 
     let mut formatter = Formatter::new(config);
     let tokens = parser.get_tokens();
     let code_blocks = parser.get_code_blocks();
-    formatter.format(&tokens, &code_blocks);
+    formatter.format(tokens, code_blocks);
+    let mut diagnostics = parser.get_diagnostics().clone();
+    diagnostics.extend(formatter.take_diagnostics());
+    diagnostics.sort_by_key(|diagnostic| diagnostic.span.bytes.start);
+    (formatter.get_output(), diagnostics)
+}
+
+fn format_range_string(
+    input: &str,
+    range: Range<usize>,
+    config: &Config,
+) -> (String, Vec<Diagnostic>) {
+    let mut parser = Parser::new(input);
+    parser.parse();
+    let blocks = top_level_blocks(parser.get_tokens());
+    let mut touched = blocks
+        .iter()
+        .filter(|block| block.start <= range.end && range.start <= block.end);
+    let Some(first) = touched.next() else {
+        return (input.to_string(), Vec::new());
+    };
+    let selected = first.start..touched.next_back().unwrap_or(first).end;
+
+    // 选中的块单独作为一篇文档格式化，块前后的空行由未选中的部分决定；
+    // 选中部分不在文档开头时，以 `---` 开始也不是 front matter
+    let selection = &input[selected.clone()];
+    let parser = if selected.start == 0 {
+        Parser::new(selection)
+    } else {
+        Parser::nested(selection)
+    };
+    let (formatted, diagnostics) = format_parsed(parser, config);
+    let origin = LineIndex::new(input).position(selected.start);
+    let diagnostics = diagnostics
+        .into_iter()
+        .map(|mut diagnostic| {
+            diagnostic.span = diagnostic.span.relative_to(selected.start, origin);
+            diagnostic
+        })
+        .collect();
+
+    let mut output = String::with_capacity(input.len());
+    output.push_str(&input[..selected.start]);
+    output.push_str(formatted.trim_matches('\n'));
+    output.push_str(&input[selected.end..]);
+    (output, diagnostics)
+}
+
+/// 按空行把 token 划分为顶层块，返回各块的字节范围。
+/// 块从所在行的行首开始（包含缩进），到最后一个非空白 token 结束；
+/// 空行之后的列表项与续行仍属于同一个列表，以保证编号与缩进能按整个列表计算。
+fn top_level_blocks(tokens: &[Token]) -> Vec<Range<usize>> {
+    let mut blocks = Vec::new();
+    let mut current: Option<Range<usize>> = None;
+    // 当前行第一个 token 的起始位置
+    let mut line_start = None;
+    // 上一个非空白 token 之后连续的换行数
+    let mut newlines = 0;
+    for token in tokens {
+        match &token.kind {
+            TokenKind::NewLine => {
+                newlines += 1;
+                line_start = None;
+            }
+            TokenKind::Text(s) if s.chars().all(char::is_whitespace) => {
+                line_start.get_or_insert(token.span.bytes.start);
+            }
+            kind => {
+                let continues_list = match kind {
+                    TokenKind::ListIndent(..) => true,
+                    TokenKind::ListItem(ListItem {
+                        level, starts_list, ..
+                    }) => *level > 0 || !starts_list,
+                    _ => false,
+                };
+                if newlines >= 2
+                    && !continues_list
+                    && let Some(block) = current.take()
+                {
+                    blocks.push(block);
+                }
+                let start = *line_start.get_or_insert(token.span.bytes.start);
+                match &mut current {
+                    Some(block) => block.end = token.span.bytes.end,
+                    None => current = Some(start..token.span.bytes.end),
+                }
+                newlines = 0;
+            }
+        }
+    }
+    blocks.extend(current);
+    blocks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT: &str = "中文English\n\n第二段123\n\n第三段abc\n";
+
+    #[test]
+    fn format_range_only_touches_selected_blocks() {
+        let second = INPUT.find("第二段").unwrap();
+        let formatted = format_range(INPUT, second..second + 3, &Config::default()).unwrap();
+        assert_eq!(formatted.output, "中文English\n\n第二段 123\n\n第三段abc\n");
+        assert!(formatted.changed);
+
+        let formatted = format_range(INPUT, 0..INPUT.len(), &Config::default()).unwrap();
+        assert_eq!(
+            formatted.output,
+            format(INPUT, &Config::default()).unwrap().output
+        );
+    }
+
+    #[test]
+    fn format_range_between_blocks_changes_nothing() {
+        let blank = INPUT.find("\n\n").unwrap() + 1;
+        let formatted = format_range(INPUT, blank..blank, &Config::default()).unwrap();
+        assert_eq!(formatted.output, INPUT);
+        assert!(!formatted.changed);
+    }
+
+    #[test]
+    fn format_range_rejects_invalid_range() {
+        assert!(format_range(INPUT, Range { start: 3, end: 1 }, &Config::default()).is_err());
+        assert!(format_range(INPUT, 0..INPUT.len() + 1, &Config::default()).is_err());
+    }
 
-    #[cfg(debug_assertions)]
-    let t3 = std::time::Instant::now();
+    #[test]
+    fn format_range_starting_with_thematic_break_is_not_front_matter() {
+        let input = "中文a\n\n---\n\n中文b\n\n---\n";
+        let start = input.find("---").unwrap();
+        let formatted = format_range(input, start..input.len(), &Config::default()).unwrap();
+        assert_eq!(formatted.output, "中文a\n\n---\n\n中文 b\n\n---\n");
 
-    #[cfg(debug_assertions)]
-    {
-        println!("Parsing time: {:?}", t2 - t1);
-        println!("Formatting time: {:?}", t3 - t2);
+        // 文档开头的 front matter 仍然按 front matter 处理
+        let input = "---\ntitle: 中文a\n---\n\n中文b\n";
+        let formatted = format_range(input, 0..input.len(), &Config::default()).unwrap();
+        assert_eq!(formatted.output, "---\ntitle: 中文a\n---\n\n中文 b\n");
     }
-    formatter.get_output()
-}
\ No newline at end of file
+}
@@ -0,0 +1,179 @@
+//! 为每个待格式化的文件确定所用的配置
+
+use anyhow::{Context, Result};
+use rustdown_formatter::{Config, ConfigLayer, find_config_file};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// 配置按优先级从低到高叠加：默认值 < 配置文件 < 环境变量 `RUSTDOWN_CONFIG` < 命令行 `--set`。
+/// 配置文件从被格式化文件所在目录开始逐级向上查找，`--config` 指定时则不再查找；
+/// 配置文件中的 `[[overrides]]` 段按被格式化文件的路径生效。
+pub struct ConfigResolver {
+    /// 优先级高于配置文件的层
+    overrides: Vec<ConfigLayer>,
+    /// `--config` 指定的配置文件
+    explicit: Option<ConfigLayer>,
+    /// 目录 -> 该目录下的文件所用的配置文件
+    dirs: HashMap<PathBuf, Option<PathBuf>>,
+    /// 已解析的配置文件
+    file_layers: HashMap<PathBuf, ConfigLayer>,
+    /// 配置文件 -> 合并后的配置（不含 overrides 段时）
+    configs: HashMap<Option<PathBuf>, Arc<Config>>,
+}
+
+impl ConfigResolver {
+    pub fn new(config_path: Option<&str>, cli_layer: ConfigLayer) -> Result<Self> {
+        let env_config = std::env::var("RUSTDOWN_CONFIG").ok();
+        Self::with_env_config(config_path, env_config.as_deref(), cli_layer)
+    }
+
+    /// 同 `new`，但 `RUSTDOWN_CONFIG` 的内容由调用方给出
+    fn with_env_config(
+        config_path: Option<&str>,
+        env_config: Option<&str>,
+        cli_layer: ConfigLayer,
+    ) -> Result<Self> {
+        let mut overrides = Vec::new();
+        if let Some(config_str) = env_config {
+            overrides.push(
+                ConfigLayer::from_json_str(config_str)
+                    .context("环境变量 RUSTDOWN_CONFIG 中的配置无效")?,
+            );
+        }
+        overrides.push(cli_layer);
+        let explicit = config_path
+            .map(|path| ConfigLayer::from_file(Path::new(path)))
+            .transpose()?;
+        Ok(ConfigResolver {
+            overrides,
+            explicit,
+            dirs: HashMap::new(),
+            file_layers: HashMap::new(),
+            configs: HashMap::new(),
+        })
+    }
+
+    /// 返回 `file` 所用的配置
+    pub fn resolve_file(&mut self, file: &Path) -> Result<Arc<Config>> {
+        // 不含目录的相对路径（如 `a.md`）的上级目录为空，视为当前目录
+        let dir = match file.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        self.resolve(dir, Some(file))
+    }
+
+    /// 返回目录 `dir` 下没有具体路径的输入（如 stdin）所用的配置，overrides 段不生效
+    pub fn resolve_dir(&mut self, dir: &Path) -> Result<Arc<Config>> {
+        self.resolve(dir, None)
+    }
+
+    fn resolve(&mut self, dir: &Path, file: Option<&Path>) -> Result<Arc<Config>> {
+        let config_file = if self.explicit.is_some() {
+            None
+        } else {
+            self.find_config_file(dir)?
+        };
+        if let Some(path) = &config_file
+            && !self.file_layers.contains_key(path)
+        {
+            let layer = ConfigLayer::from_file(path)?;
+            self.file_layers.insert(path.clone(), layer);
+        }
+        let file_layer = match &config_file {
+            Some(path) => self.file_layers.get(path),
+            None => self.explicit.as_ref(),
+        };
+
+        // 含 overrides 段的配置因文件而异，不做缓存
+        if let Some(layer) = file_layer
+            && let Some(file) = file
+            && layer.has_overrides()
+        {
+            let layer = layer.for_path(file);
+            return Ok(Arc::new(Config::from_layers(
+                std::iter::once(&layer).chain(&self.overrides),
+            )?));
+        }
+
+        if let Some(config) = self.configs.get(&config_file) {
+            return Ok(config.clone());
+        }
+        let config = Arc::new(Config::from_layers(
+            file_layer.into_iter().chain(&self.overrides),
+        )?);
+        self.configs.insert(config_file, config.clone());
+        Ok(config)
+    }
+
+    fn find_config_file(&mut self, dir: &Path) -> Result<Option<PathBuf>> {
+        if let Some(config_file) = self.dirs.get(dir) {
+            return Ok(config_file.clone());
+        }
+        // 相对路径无法向上越过当前目录，先转为绝对路径
+        let config_file = find_config_file(&std::path::absolute(dir)?);
+        self.dirs.insert(dir.to_path_buf(), config_file.clone());
+        Ok(config_file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// 目录中的 rustdown.toml 关闭中英文之间的空格，`--config` 指定的文件关闭中文与数字之间的空格
+    fn config_dir(name: &str) -> (PathBuf, String) {
+        let dir = std::env::temp_dir().join(format!("rustdown-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("rustdown.toml"),
+            "space_between_zh_and_en = false\n",
+        )
+        .unwrap();
+        let explicit = dir.join("explicit.json");
+        fs::write(&explicit, r#"{"space_between_zh_and_num": false}"#).unwrap();
+        (dir, explicit.to_string_lossy().into_owned())
+    }
+
+    #[test]
+    fn explicit_config_replaces_discovered_file() {
+        let (dir, explicit) = config_dir("explicit");
+        let mut resolver =
+            ConfigResolver::with_env_config(None, None, ConfigLayer::default()).unwrap();
+        let config = resolver.resolve_dir(&dir).unwrap();
+        assert!(!config.space_between_zh_and_en && config.space_between_zh_and_num);
+
+        let mut resolver =
+            ConfigResolver::with_env_config(Some(&explicit), None, ConfigLayer::default()).unwrap();
+        let config = resolver.resolve_dir(&dir).unwrap();
+        assert!(config.space_between_zh_and_en && !config.space_between_zh_and_num);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn env_config_overrides_config_file() {
+        let (dir, explicit) = config_dir("env");
+        let env = r#"{"space_between_zh_and_num": true, "space_between_zh_and_en": false}"#;
+        let mut resolver =
+            ConfigResolver::with_env_config(Some(&explicit), Some(env), ConfigLayer::default())
+                .unwrap();
+        let config = resolver.resolve_dir(&dir).unwrap();
+        assert!(!config.space_between_zh_and_en && config.space_between_zh_and_num);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn set_overrides_env_config() {
+        let (dir, _) = config_dir("set");
+        let mut cli_layer = ConfigLayer::default();
+        cli_layer
+            .set("space_between_zh_and_en", true.into())
+            .unwrap();
+        let env = r#"{"space_between_zh_and_en": false}"#;
+        let mut resolver = ConfigResolver::with_env_config(None, Some(env), cli_layer).unwrap();
+        assert!(resolver.resolve_dir(&dir).unwrap().space_between_zh_and_en);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
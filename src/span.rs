@@ -0,0 +1,83 @@
+use serde::Serialize;
+use std::cell::Cell;
+use std::ops::Range;
+
+/// 源码中的位置。行与列均从 0 开始，列按字符（Unicode 标量值）计
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Serialize)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// 一段源码范围
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize)]
+pub struct Span {
+    /// 字节范围
+    pub bytes: Range<usize>,
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Span {
+    /// 将以嵌套文档（如标题文本、markdown 代码块）起点为原点的范围，
+    /// 换算为外层文档中的范围；`origin` 为嵌套文档起点在外层文档中的位置
+    pub(crate) fn relative_to(&self, origin_byte: usize, origin: Position) -> Span {
+        let shift = |position: Position| Position {
+            line: position.line + origin.line,
+            column: if position.line == 0 {
+                position.column + origin.column
+            } else {
+                position.column
+            },
+        };
+        Span {
+            bytes: self.bytes.start + origin_byte..self.bytes.end + origin_byte,
+            start: shift(self.start),
+            end: shift(self.end),
+        }
+    }
+}
+
+/// 字节偏移到行列位置的换算
+pub(crate) struct LineIndex<'a> {
+    input: &'a str,
+    /// 每一行起始处的字节偏移
+    line_starts: Vec<usize>,
+    /// 上一次换算的结果 (字节偏移, 行, 列)。解析器大体按顺序查询，
+    /// 从这里继续数字符可以避免在很长的行上反复从行首数起
+    cursor: Cell<(usize, usize, usize)>,
+}
+
+impl<'a> LineIndex<'a> {
+    pub(crate) fn new(input: &'a str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(input.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        LineIndex {
+            input,
+            line_starts,
+            cursor: Cell::new((0, 0, 0)),
+        }
+    }
+
+    pub(crate) fn position(&self, offset: usize) -> Position {
+        let line = self.line_starts.partition_point(|&start| start <= offset) - 1;
+        let line_start = self.line_starts[line];
+        let (cursor_offset, cursor_line, cursor_column) = self.cursor.get();
+        let column = if cursor_line == line && cursor_offset <= offset {
+            cursor_column + self.input[cursor_offset..offset].chars().count()
+        } else {
+            self.input[line_start..offset].chars().count()
+        };
+        self.cursor.set((offset, line, column));
+        Position { line, column }
+    }
+
+    pub(crate) fn span(&self, bytes: Range<usize>) -> Span {
+        Span {
+            start: self.position(bytes.start),
+            end: self.position(bytes.end),
+            bytes,
+        }
+    }
+}